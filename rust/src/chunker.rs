@@ -0,0 +1,139 @@
+//! Content-defined chunking so that inserting or deleting bytes in the
+//! middle of a file only perturbs the chunks touching the edit, instead of
+//! reshuffling every chunk after it the way fixed-size slicing would.
+//!
+//! This is a FastCDC-style rolling Gear hash: `hash = (hash << 1) +
+//! GEAR[byte]` is maintained over a window of bytes, and a chunk boundary
+//! falls wherever `hash & mask == 0`. Normalizing the mask around the
+//! target average size (stricter below it, looser above it) keeps the
+//! resulting chunk sizes from spreading out into a long geometric tail.
+
+/// Chunking never considers a cut before this many bytes.
+const MIN_SIZE: usize = 2 * 1024;
+/// The mask switches from `MASK_SMALL` to `MASK_LARGE` once a chunk reaches
+/// this size, nudging the distribution to center around it.
+const AVG_SIZE: usize = 8 * 1024;
+/// A cut is forced here regardless of the hash, bounding worst-case chunk size.
+const MAX_SIZE: usize = 64 * 1024;
+
+/// Stricter mask (more one-bits, lower match probability) used below
+/// `AVG_SIZE` so chunks don't get cut too early.
+const MASK_SMALL: u64 = (1 << 15) - 1;
+/// Looser mask (fewer one-bits, higher match probability) used above
+/// `AVG_SIZE` so chunks don't run on too long.
+const MASK_LARGE: u64 = (1 << 11) - 1;
+
+/// Per-byte mixing values for the rolling hash. Fixed and arbitrary, not
+/// secret - only their spread over 0..256 matters, so they're generated
+/// with a small splitmix64 sequence instead of a 256-entry literal.
+const GEAR: [u64; 256] = gear_table();
+
+const fn gear_table() -> [u64; 256] {
+    let mut table = [0u64; 256];
+    let mut state: u64 = 0x9E3779B97F4A7C15;
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^= z >> 31;
+        table[i] = z;
+        i += 1;
+    }
+    table
+}
+
+/// Splits `data` into content-defined chunks, each between `MIN_SIZE` and
+/// `MAX_SIZE` bytes (the final chunk may be shorter).
+pub fn split(data: &[u8]) -> Vec<&[u8]> {
+    let mut chunks = Vec::new();
+    let mut rest = data;
+    while !rest.is_empty() {
+        let cut = next_cut(rest);
+        let (chunk, remainder) = rest.split_at(cut);
+        chunks.push(chunk);
+        rest = remainder;
+    }
+    chunks
+}
+
+/// Finds the length of the next chunk at the front of `data`.
+fn next_cut(data: &[u8]) -> usize {
+    if data.len() <= MIN_SIZE {
+        return data.len();
+    }
+    let max = data.len().min(MAX_SIZE);
+    let mut hash: u64 = 0;
+    let mut i = MIN_SIZE;
+    while i < max {
+        hash = (hash << 1).wrapping_add(GEAR[data[i] as usize]);
+        let mask = if i < AVG_SIZE { MASK_SMALL } else { MASK_LARGE };
+        if hash & mask == 0 {
+            return i + 1;
+        }
+        i += 1;
+    }
+    max
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_input_produces_no_chunks() {
+        assert!(split(&[]).is_empty());
+    }
+
+    #[test]
+    fn small_input_is_a_single_chunk() {
+        let data = vec![42u8; MIN_SIZE / 2];
+        let chunks = split(&data);
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].len(), data.len());
+    }
+
+    #[test]
+    fn chunks_reassemble_to_the_original_bytes() {
+        let data: Vec<u8> = (0..300_000u32).map(|i| (i % 256) as u8).collect();
+        let chunks = split(&data);
+        assert!(chunks.len() > 1);
+        let reassembled: Vec<u8> = chunks.concat();
+        assert_eq!(reassembled, data);
+    }
+
+    #[test]
+    fn no_chunk_exceeds_max_size() {
+        let data: Vec<u8> = (0..300_000u32).map(|i| (i % 256) as u8).collect();
+        for chunk in split(&data) {
+            assert!(chunk.len() <= MAX_SIZE);
+        }
+    }
+
+    #[test]
+    fn inserting_bytes_only_perturbs_nearby_chunks() {
+        // Pseudo-random bytes: short-period input never matches a mask, so
+        // every cut would be forced at MAX_SIZE and shift with the insertion.
+        let mut state: u64 = 1;
+        let data: Vec<u8> = (0..300_000)
+            .map(|_| {
+                state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                (state >> 56) as u8
+            })
+            .collect();
+        let mut edited = data.clone();
+        edited.splice(150_000..150_000, [9u8; 37]);
+
+        let before = split(&data);
+        let after = split(&edited);
+
+        let unchanged_suffix = before
+            .iter()
+            .rev()
+            .zip(after.iter().rev())
+            .take_while(|(a, b)| a == b)
+            .count();
+        assert!(unchanged_suffix > 0);
+    }
+}
@@ -0,0 +1,164 @@
+use anyhow::{anyhow, Result};
+use argon2::Argon2;
+use chacha20poly1305::{
+    aead::{Aead, KeyInit},
+    XChaCha20Poly1305, XNonce,
+};
+use rand_core::{OsRng, RngCore};
+
+pub const KEY_LEN: usize = 32;
+pub const NONCE_LEN: usize = 24;
+
+/// An XChaCha20-Poly1305 key used to encrypt/decrypt individual row values.
+/// Ciphertexts are `nonce || ciphertext+tag`, with a fresh random nonce per
+/// call so the same plaintext never produces the same bytes twice.
+#[derive(Clone)]
+pub struct Cipher {
+    key: [u8; KEY_LEN],
+}
+
+impl Cipher {
+    pub fn from_key(key: [u8; KEY_LEN]) -> Self {
+        Cipher { key }
+    }
+
+    /// Derive a key from a low-entropy passphrase with Argon2 (memory-hard),
+    /// salted per-database so two databases with the same passphrase don't
+    /// share a key.
+    pub fn derive_from_passphrase(passphrase: &str, salt: &[u8]) -> Result<Self> {
+        let mut key = [0u8; KEY_LEN];
+        Argon2::default()
+            .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+            .map_err(|e| anyhow!("key derivation failed: {e}"))?;
+        Ok(Cipher { key })
+    }
+
+    pub fn encrypt(&self, plaintext: &[u8]) -> Vec<u8> {
+        let cipher = XChaCha20Poly1305::new((&self.key).into());
+        let mut nonce_bytes = [0u8; NONCE_LEN];
+        OsRng.fill_bytes(&mut nonce_bytes);
+        let nonce = XNonce::from_slice(&nonce_bytes);
+        // XChaCha20-Poly1305 only fails to encrypt on buffer-too-large inputs,
+        // which never happens for the row- and chunk-sized values this is used on.
+        let ciphertext = cipher.encrypt(nonce, plaintext).expect("encryption failed");
+        let mut out = Vec::with_capacity(NONCE_LEN + ciphertext.len());
+        out.extend_from_slice(&nonce_bytes);
+        out.extend_from_slice(&ciphertext);
+        out
+    }
+
+    pub fn decrypt(&self, blob: &[u8]) -> Result<Vec<u8>> {
+        if blob.len() < NONCE_LEN {
+            return Err(anyhow!("ciphertext too short"));
+        }
+        let (nonce_bytes, ciphertext) = blob.split_at(NONCE_LEN);
+        let cipher = XChaCha20Poly1305::new((&self.key).into());
+        let nonce = XNonce::from_slice(nonce_bytes);
+        cipher
+            .decrypt(nonce, ciphertext)
+            .map_err(|_| anyhow!("decryption failed: bad key or corrupt data"))
+    }
+}
+
+/// Domain-separation context for `blake3::derive_key`, so a chunk key can
+/// never collide with a key derived the same way for some other purpose.
+const CHUNK_KEY_CONTEXT: &str = "puppycloud chunk key v1";
+
+/// Encrypts chunk files convergently: the key for a given plaintext is
+/// derived from a master key plus the plaintext's own content hash, so the
+/// same content always encrypts under the same key (letting `chunk_id`
+/// stay the hash of the plaintext, and dedup keep working) while content
+/// nobody has ever stored still gets its own independent key.
+#[derive(Clone)]
+pub struct CipherEngine {
+    master_key: [u8; KEY_LEN],
+}
+
+impl CipherEngine {
+    pub fn derive_from_passphrase(passphrase: &str, salt: &[u8]) -> Result<Self> {
+        let mut master_key = [0u8; KEY_LEN];
+        Argon2::default()
+            .hash_password_into(passphrase.as_bytes(), salt, &mut master_key)
+            .map_err(|e| anyhow!("key derivation failed: {e}"))?;
+        Ok(CipherEngine { master_key })
+    }
+
+    /// Encrypts `plaintext` whose content address is `content_id`.
+    pub fn encrypt_chunk(&self, content_id: &str, plaintext: &[u8]) -> Vec<u8> {
+        self.chunk_cipher(content_id).encrypt(plaintext)
+    }
+
+    /// Decrypts a blob previously produced by `encrypt_chunk` for the same
+    /// `content_id`, verifying its AEAD tag in the process.
+    pub fn decrypt_chunk(&self, content_id: &str, blob: &[u8]) -> Result<Vec<u8>> {
+        self.chunk_cipher(content_id).decrypt(blob)
+    }
+
+    fn chunk_cipher(&self, content_id: &str) -> Cipher {
+        let mut key_material = Vec::with_capacity(KEY_LEN + content_id.len());
+        key_material.extend_from_slice(&self.master_key);
+        key_material.extend_from_slice(content_id.as_bytes());
+        Cipher::from_key(blake3::derive_key(CHUNK_KEY_CONTEXT, &key_material))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips() {
+        let cipher = Cipher::from_key([7u8; KEY_LEN]);
+        let blob = cipher.encrypt(b"super secret");
+        assert_eq!(cipher.decrypt(&blob).unwrap(), b"super secret");
+    }
+
+    #[test]
+    fn same_plaintext_encrypts_differently_each_time() {
+        let cipher = Cipher::from_key([1u8; KEY_LEN]);
+        let a = cipher.encrypt(b"hello");
+        let b = cipher.encrypt(b"hello");
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn wrong_key_fails_to_decrypt() {
+        let a = Cipher::from_key([1u8; KEY_LEN]);
+        let b = Cipher::from_key([2u8; KEY_LEN]);
+        let blob = a.encrypt(b"hello");
+        assert!(b.decrypt(&blob).is_err());
+    }
+
+    #[test]
+    fn chunk_cipher_decrypts_its_own_ciphertext() {
+        let engine = CipherEngine::derive_from_passphrase("hunter2", b"0123456789abcdef").unwrap();
+        let blob = engine.encrypt_chunk("deadbeef", b"chunk bytes");
+        assert_eq!(engine.decrypt_chunk("deadbeef", &blob).unwrap(), b"chunk bytes");
+    }
+
+    #[test]
+    fn chunk_cipher_is_convergent_on_content_id_and_master_key() {
+        let a = CipherEngine::derive_from_passphrase("hunter2", b"0123456789abcdef").unwrap();
+        let b = CipherEngine::derive_from_passphrase("hunter2", b"0123456789abcdef").unwrap();
+        // Different nonces, so the ciphertexts differ, but both were produced
+        // under the same derived key and so decrypt under either engine.
+        let blob = a.encrypt_chunk("deadbeef", b"chunk bytes");
+        assert_eq!(b.decrypt_chunk("deadbeef", &blob).unwrap(), b"chunk bytes");
+    }
+
+    #[test]
+    fn chunk_cipher_differs_per_content_id() {
+        let engine = CipherEngine::derive_from_passphrase("hunter2", b"0123456789abcdef").unwrap();
+        let a = engine.encrypt_chunk("id-a", b"chunk bytes");
+        assert!(engine.decrypt_chunk("id-b", &a).is_err());
+    }
+
+    #[test]
+    fn derivation_is_deterministic_for_the_same_salt() {
+        let salt = b"0123456789abcdef";
+        let a = Cipher::derive_from_passphrase("hunter2", salt).unwrap();
+        let b = Cipher::derive_from_passphrase("hunter2", salt).unwrap();
+        let blob = a.encrypt(b"hello");
+        assert_eq!(b.decrypt(&blob).unwrap(), b"hello");
+    }
+}
@@ -0,0 +1,546 @@
+//! Read-only FUSE mount exposing stored manifests as an ordinary filesystem.
+//!
+//! The mount has a single root: a manifest id (or, more usually, a
+//! [`crate::db::GenerationRow::name`]'s latest generation) resolved once at
+//! mount time. If that manifest is directory-style (its `entries` field is
+//! populated) its children are listed as directory entries, each lazily
+//! resolved to its own manifest the first time it's looked up; if it's a
+//! plain file the mount root is that file itself. Reads map the requested
+//! byte range onto the manifest's `ChunkRef` list and fetch/decrypt only the
+//! chunks that overlap it, keeping a small LRU of recently read chunks warm
+//! since FUSE tends to re-read the tail of a chunk across several small
+//! `read` calls.
+
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use fuser::{
+    FileAttr, FileType, Filesystem, MountOption, ReplyAttr, ReplyData, ReplyDirectory,
+    ReplyEntry, ReplyOpen, Request,
+};
+use libc::ENOENT;
+
+use crate::{crypto::CipherEngine, db, read_chunk, ChunkRef, FileManifest};
+
+const ATTR_TTL: Duration = Duration::from_secs(1);
+const CHUNK_CACHE_CAPACITY: usize = 64;
+const ROOT_INODE: u64 = 1;
+
+/// Resolves `root` to the manifest id it should expose: first as a
+/// generation name (its latest generation), falling back to treating it as
+/// a literal manifest id.
+pub(crate) fn resolve_root_manifest_id(
+    conn: &rusqlite::Connection,
+    root: &str,
+) -> rusqlite::Result<Option<String>> {
+    let generations = db::list_generations(conn, root)?;
+    if let Some(latest) = generations.last() {
+        return Ok(Some(latest.manifest_id.clone()));
+    }
+    Ok(db::get_manifest(conn, root)?.map(|_| root.to_string()))
+}
+
+/// Mounts `root_manifest_id` at `mount_point`, blocking the calling thread
+/// (and therefore the calling OS thread, not the async runtime) until the
+/// mount is unmounted.
+pub(crate) fn mount(
+    mount_point: &str,
+    db: db::Pool,
+    chunk_cipher: Option<CipherEngine>,
+    data_root: PathBuf,
+    root_manifest_id: String,
+    rt: tokio::runtime::Handle,
+) -> std::io::Result<()> {
+    let fs = PuppyFs::new(db, chunk_cipher, data_root, root_manifest_id, rt);
+    let options = vec![MountOption::RO, MountOption::FSName("puppycloud".into())];
+    fuser::mount2(fs, mount_point, &options)
+}
+
+/// A node the filesystem has handed out an inode for: either a directory
+/// (listing manifest entries by name) or a file backed by a manifest.
+enum Node {
+    Dir { entries: Vec<ManifestChild> },
+    File { manifest: FileManifest },
+}
+
+#[derive(Clone)]
+struct ManifestChild {
+    name: String,
+    ino: u64,
+}
+
+struct Inodes {
+    next: u64,
+    by_ino: HashMap<u64, Node>,
+    /// Manifest id behind each child inode allocated while listing its
+    /// parent, so `node()` knows which manifest to load on first visit.
+    manifest_id_by_ino: HashMap<u64, String>,
+}
+
+impl Inodes {
+    fn alloc(&mut self) -> u64 {
+        let ino = self.next;
+        self.next += 1;
+        ino
+    }
+}
+
+/// Small fixed-capacity LRU of decrypted chunk bytes, avoiding a disk round
+/// trip (and a re-decrypt) for the repeated small reads a single `cat` or
+/// `read()` loop tends to issue against the tail of the same chunk.
+struct ChunkCache {
+    capacity: usize,
+    order: Vec<String>,
+    bytes: HashMap<String, Vec<u8>>,
+}
+
+impl ChunkCache {
+    fn new(capacity: usize) -> Self {
+        ChunkCache { capacity, order: Vec::new(), bytes: HashMap::new() }
+    }
+
+    fn get(&mut self, id: &str) -> Option<Vec<u8>> {
+        if let Some(data) = self.bytes.get(id) {
+            let data = data.clone();
+            self.touch(id);
+            Some(data)
+        } else {
+            None
+        }
+    }
+
+    fn touch(&mut self, id: &str) {
+        if let Some(pos) = self.order.iter().position(|k| k == id) {
+            let k = self.order.remove(pos);
+            self.order.push(k);
+        }
+    }
+
+    fn insert(&mut self, id: String, data: Vec<u8>) {
+        if self.bytes.len() >= self.capacity && !self.bytes.contains_key(&id) {
+            if let Some(oldest) = self.order.first().cloned() {
+                self.order.remove(0);
+                self.bytes.remove(&oldest);
+            }
+        }
+        self.order.push(id.clone());
+        self.bytes.insert(id, data);
+    }
+}
+
+pub(crate) struct PuppyFs {
+    db: db::Pool,
+    chunk_cipher: Option<CipherEngine>,
+    data_root: PathBuf,
+    root_manifest_id: String,
+    rt: tokio::runtime::Handle,
+    inodes: Mutex<Inodes>,
+    cache: Mutex<ChunkCache>,
+}
+
+impl PuppyFs {
+    fn new(
+        db: db::Pool,
+        chunk_cipher: Option<CipherEngine>,
+        data_root: PathBuf,
+        root_manifest_id: String,
+        rt: tokio::runtime::Handle,
+    ) -> Self {
+        PuppyFs {
+            db,
+            chunk_cipher,
+            data_root,
+            root_manifest_id,
+            rt,
+            inodes: Mutex::new(Inodes {
+                next: ROOT_INODE + 1,
+                by_ino: HashMap::new(),
+                manifest_id_by_ino: HashMap::new(),
+            }),
+            cache: Mutex::new(ChunkCache::new(CHUNK_CACHE_CAPACITY)),
+        }
+    }
+
+    fn load_manifest(&self, manifest_id: &str) -> Option<FileManifest> {
+        let conn = self.db.get().ok()?;
+        let json = db::get_manifest(&conn, manifest_id).ok()??;
+        serde_json::from_str(&json).ok()
+    }
+
+    /// Resolves `ino` to a `Node`, populating it from the database the first
+    /// time this inode is visited (the root from the manifest id chosen at
+    /// mount time, every other inode from the id recorded for it when its
+    /// parent was loaded).
+    fn node(&self, ino: u64) -> Option<()> {
+        let mut inodes = self.inodes.lock().unwrap();
+        if inodes.by_ino.contains_key(&ino) {
+            return Some(());
+        }
+        let manifest_id = if ino == ROOT_INODE {
+            self.root_manifest_id.clone()
+        } else {
+            inodes.manifest_id_by_ino.get(&ino)?.clone()
+        };
+        drop(inodes);
+        let man = self.load_manifest(&manifest_id)?;
+        let node = match man.entries {
+            Some(entries) => {
+                let mut inodes = self.inodes.lock().unwrap();
+                let children = entries
+                    .into_iter()
+                    .map(|e| {
+                        let child_ino = inodes.alloc();
+                        inodes.manifest_id_by_ino.insert(child_ino, e.manifest_id);
+                        ManifestChild { name: e.name, ino: child_ino }
+                    })
+                    .collect();
+                Node::Dir { entries: children }
+            }
+            None => Node::File { manifest: man },
+        };
+        self.inodes.lock().unwrap().by_ino.insert(ino, node);
+        Some(())
+    }
+
+    fn attr_for(&self, ino: u64) -> Option<FileAttr> {
+        self.node(ino)?;
+        let inodes = self.inodes.lock().unwrap();
+        let node = inodes.by_ino.get(&ino)?;
+        Some(match node {
+            Node::Dir { .. } => dir_attr(ino),
+            Node::File { manifest } => file_attr(ino, manifest),
+        })
+    }
+}
+
+fn dir_attr(ino: u64) -> FileAttr {
+    let now = SystemTime::now();
+    FileAttr {
+        ino,
+        size: 0,
+        blocks: 0,
+        atime: now,
+        mtime: now,
+        ctime: now,
+        crtime: now,
+        kind: FileType::Directory,
+        perm: 0o555,
+        nlink: 2,
+        uid: 0,
+        gid: 0,
+        rdev: 0,
+        blksize: 512,
+        flags: 0,
+    }
+}
+
+fn file_attr(ino: u64, manifest: &FileManifest) -> FileAttr {
+    let mtime = UNIX_EPOCH + Duration::from_secs(manifest.created_ts.unix_timestamp().max(0) as u64);
+    FileAttr {
+        ino,
+        size: manifest.total_size,
+        blocks: (manifest.total_size + 511) / 512,
+        atime: mtime,
+        mtime,
+        ctime: mtime,
+        crtime: mtime,
+        kind: FileType::RegularFile,
+        perm: 0o444,
+        nlink: 1,
+        uid: 0,
+        gid: 0,
+        rdev: 0,
+        blksize: 512,
+        flags: 0,
+    }
+}
+
+/// Picks the subset of `chunks` overlapping `[start, end)` and how much of
+/// each to take, mirroring `plan_chunk_slices` in `main.rs` (kept separate
+/// since the byte-range convention here is FUSE's `[offset, offset+size)`
+/// rather than an inclusive HTTP `Range`).
+fn slices_for(chunks: &[ChunkRef], start: u64, end: u64) -> Vec<(String, usize, usize)> {
+    let mut plan = Vec::new();
+    let mut offset = 0u64;
+    for c in chunks {
+        let chunk_start = offset;
+        let chunk_end = offset + c.size as u64;
+        offset = chunk_end;
+        if chunk_end <= start || chunk_start >= end {
+            continue;
+        }
+        let skip = start.saturating_sub(chunk_start) as usize;
+        let take = (end.min(chunk_end) - chunk_start) as usize - skip;
+        plan.push((c.id.clone(), skip, take));
+    }
+    plan
+}
+
+impl Filesystem for PuppyFs {
+    fn lookup(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        if self.node(parent).is_none() {
+            reply.error(ENOENT);
+            return;
+        }
+        let name = match name.to_str() {
+            Some(n) => n,
+            None => return reply.error(ENOENT),
+        };
+        let child_ino = {
+            let inodes = self.inodes.lock().unwrap();
+            match inodes.by_ino.get(&parent) {
+                Some(Node::Dir { entries }) => entries.iter().find(|e| e.name == name).map(|e| e.ino),
+                _ => None,
+            }
+        };
+        match child_ino.and_then(|ino| self.attr_for(ino).map(|a| (ino, a))) {
+            Some((_, attr)) => reply.entry(&ATTR_TTL, &attr, 0),
+            None => reply.error(ENOENT),
+        }
+    }
+
+    fn getattr(&mut self, _req: &Request<'_>, ino: u64, _fh: Option<u64>, reply: ReplyAttr) {
+        match self.attr_for(ino) {
+            Some(attr) => reply.attr(&ATTR_TTL, &attr),
+            None => reply.error(ENOENT),
+        }
+    }
+
+    fn open(&mut self, _req: &Request<'_>, ino: u64, _flags: i32, reply: ReplyOpen) {
+        if self.node(ino).is_none() {
+            reply.error(ENOENT);
+            return;
+        }
+        reply.opened(0, 0);
+    }
+
+    fn read(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        size: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyData,
+    ) {
+        if self.node(ino).is_none() {
+            reply.error(ENOENT);
+            return;
+        }
+        let manifest = {
+            let inodes = self.inodes.lock().unwrap();
+            match inodes.by_ino.get(&ino) {
+                Some(Node::File { manifest }) => {
+                    FileManifest {
+                        total_size: manifest.total_size,
+                        chunks: manifest.chunks.iter().map(|c| ChunkRef { id: c.id.clone(), size: c.size }).collect(),
+                        mime: manifest.mime.clone(),
+                        created_ts: manifest.created_ts,
+                        entries: None,
+                    }
+                }
+                _ => return reply.error(libc::EISDIR),
+            }
+        };
+
+        let start = offset.max(0) as u64;
+        let end = (start + size as u64).min(manifest.total_size);
+        if start >= end {
+            reply.data(&[]);
+            return;
+        }
+
+        let mut out = Vec::with_capacity((end - start) as usize);
+        for (id, skip, take) in slices_for(&manifest.chunks, start, end) {
+            let data = match self.cache.lock().unwrap().get(&id) {
+                Some(data) => data,
+                None => {
+                    let fetched = self
+                        .rt
+                        .block_on(read_chunk(&self.data_root, &self.chunk_cipher, &id));
+                    match fetched {
+                        Ok(Some(data)) => {
+                            self.cache.lock().unwrap().insert(id.clone(), data.clone());
+                            data
+                        }
+                        _ => return reply.error(libc::EIO),
+                    }
+                }
+            };
+            let from = skip.min(data.len());
+            let to = (skip + take).min(data.len());
+            out.extend_from_slice(&data[from..to]);
+        }
+        reply.data(&out);
+    }
+
+    fn readdir(&mut self, _req: &Request<'_>, ino: u64, _fh: u64, offset: i64, mut reply: ReplyDirectory) {
+        if self.node(ino).is_none() {
+            reply.error(ENOENT);
+            return;
+        }
+        let inodes = self.inodes.lock().unwrap();
+        let children = match inodes.by_ino.get(&ino) {
+            Some(Node::Dir { entries }) => entries.clone(),
+            _ => return reply.error(libc::ENOTDIR),
+        };
+        drop(inodes);
+
+        let mut rows: Vec<(u64, FileType, String)> = vec![
+            (ino, FileType::Directory, ".".to_string()),
+            (ino, FileType::Directory, "..".to_string()),
+        ];
+        for child in children {
+            // Resolve the child before inspecting it: an inode allocated while
+            // listing its parent isn't in `by_ino` until `node()` loads it, so
+            // skipping this would report every unvisited subdirectory as a file.
+            self.node(child.ino);
+            let inodes = self.inodes.lock().unwrap();
+            let kind = match inodes.by_ino.get(&child.ino) {
+                Some(Node::Dir { .. }) => FileType::Directory,
+                _ => FileType::RegularFile,
+            };
+            drop(inodes);
+            rows.push((child.ino, kind, child.name));
+        }
+
+        for (i, (ino, kind, name)) in rows.into_iter().enumerate().skip(offset as usize) {
+            if reply.add(ino, (i + 1) as i64, kind, name) {
+                break;
+            }
+        }
+        reply.ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ManifestEntry;
+    use r2d2_sqlite::SqliteConnectionManager;
+
+    fn test_fs(root_manifest_id: &str) -> (PuppyFs, tokio::runtime::Runtime) {
+        let manager = SqliteConnectionManager::memory();
+        let pool = r2d2::Pool::builder().max_size(1).build(manager).unwrap();
+        db::init_schema(&pool.get().unwrap()).unwrap();
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let fs = PuppyFs::new(pool, None, PathBuf::from("/tmp"), root_manifest_id.to_string(), rt.handle().clone());
+        (fs, rt)
+    }
+
+    fn store_manifest(fs: &PuppyFs, id: &str, man: &FileManifest) {
+        let conn = fs.db.get().unwrap();
+        db::upsert_manifest(&conn, id, &serde_json::to_string(man).unwrap()).unwrap();
+    }
+
+    fn dir_manifest(entries: &[(&str, &str)]) -> FileManifest {
+        FileManifest {
+            total_size: 0,
+            chunks: vec![],
+            mime: None,
+            created_ts: time::OffsetDateTime::UNIX_EPOCH,
+            entries: Some(
+                entries
+                    .iter()
+                    .map(|(name, manifest_id)| ManifestEntry { name: name.to_string(), manifest_id: manifest_id.to_string() })
+                    .collect(),
+            ),
+        }
+    }
+
+    fn file_manifest(total_size: u64) -> FileManifest {
+        FileManifest { total_size, chunks: vec![], mime: None, created_ts: time::OffsetDateTime::UNIX_EPOCH, entries: None }
+    }
+
+    #[test]
+    fn slices_for_splits_a_range_across_chunk_boundaries() {
+        let chunks = vec![
+            ChunkRef { id: "a".into(), size: 10 },
+            ChunkRef { id: "b".into(), size: 10 },
+            ChunkRef { id: "c".into(), size: 10 },
+        ];
+        let plan = slices_for(&chunks, 5, 25);
+        assert_eq!(
+            plan,
+            vec![("a".to_string(), 5, 5), ("b".to_string(), 0, 10), ("c".to_string(), 0, 5)]
+        );
+    }
+
+    #[test]
+    fn slices_for_skips_chunks_entirely_outside_the_range() {
+        let chunks = vec![ChunkRef { id: "a".into(), size: 10 }, ChunkRef { id: "b".into(), size: 10 }];
+        assert_eq!(slices_for(&chunks, 10, 20), vec![("b".to_string(), 0, 10)]);
+        assert_eq!(slices_for(&chunks, 0, 5), vec![("a".to_string(), 0, 5)]);
+        assert_eq!(slices_for(&chunks, 0, 0), Vec::<(String, usize, usize)>::new());
+    }
+
+    #[test]
+    fn chunk_cache_evicts_the_least_recently_used_entry() {
+        let mut cache = ChunkCache::new(2);
+        cache.insert("a".to_string(), vec![1]);
+        cache.insert("b".to_string(), vec![2]);
+        cache.get("a"); // touch a, leaving b as the least recently used
+        cache.insert("c".to_string(), vec![3]);
+
+        assert!(cache.get("a").is_some());
+        assert!(cache.get("b").is_none());
+        assert!(cache.get("c").is_some());
+    }
+
+    #[test]
+    fn node_resolves_a_child_allocated_but_not_yet_visited() {
+        let (fs, _rt) = test_fs("root");
+        store_manifest(&fs, "root", &dir_manifest(&[("sub", "sub-manifest")]));
+        store_manifest(&fs, "sub-manifest", &dir_manifest(&[]));
+
+        assert!(fs.node(ROOT_INODE).is_some());
+        let child_ino = {
+            let inodes = fs.inodes.lock().unwrap();
+            match inodes.by_ino.get(&ROOT_INODE) {
+                Some(Node::Dir { entries }) => entries[0].ino,
+                _ => panic!("root did not resolve to a directory"),
+            }
+        };
+
+        // Allocating the child inode while listing its parent only records its
+        // manifest id; it isn't in `by_ino` until `node()` loads it. This is
+        // the state readdir must account for instead of treating a miss here
+        // as "not a directory".
+        assert!(!fs.inodes.lock().unwrap().by_ino.contains_key(&child_ino));
+
+        assert!(fs.node(child_ino).is_some());
+        let inodes = fs.inodes.lock().unwrap();
+        assert!(matches!(inodes.by_ino.get(&child_ino), Some(Node::Dir { .. })));
+    }
+
+    #[test]
+    fn attr_for_reports_a_resolved_child_directory_correctly() {
+        let (fs, _rt) = test_fs("root");
+        store_manifest(&fs, "root", &dir_manifest(&[("sub", "sub-manifest"), ("f", "file-manifest")]));
+        store_manifest(&fs, "sub-manifest", &dir_manifest(&[]));
+        store_manifest(&fs, "file-manifest", &file_manifest(42));
+
+        assert!(fs.node(ROOT_INODE).is_some());
+        let (sub_ino, file_ino) = {
+            let inodes = fs.inodes.lock().unwrap();
+            match inodes.by_ino.get(&ROOT_INODE) {
+                Some(Node::Dir { entries }) => {
+                    let sub = entries.iter().find(|e| e.name == "sub").unwrap().ino;
+                    let file = entries.iter().find(|e| e.name == "f").unwrap().ino;
+                    (sub, file)
+                }
+                _ => panic!("root did not resolve to a directory"),
+            }
+        };
+
+        assert_eq!(fs.attr_for(sub_ino).unwrap().kind, FileType::Directory);
+        let file_attr = fs.attr_for(file_ino).unwrap();
+        assert_eq!(file_attr.kind, FileType::RegularFile);
+        assert_eq!(file_attr.size, 42);
+    }
+}
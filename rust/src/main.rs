@@ -1,47 +1,38 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     net::SocketAddr,
     path::{Path, PathBuf},
     sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
 };
 
 use anyhow::Result;
 use axum::{
+    body::Body,
     extract::{Multipart, Path as AxPath, State},
-    http::StatusCode,
-    response::Html,
-    routing::{get, post},
+    http::{HeaderMap, StatusCode},
+    response::{Html, IntoResponse, Response},
+    routing::{get, post, put},
     Json, Router,
 };
 use base64::Engine;
 use bytes::Bytes;
 use clap::Parser;
+use futures::StreamExt;
 use rusqlite::{params, Connection};
 use serde::{Deserialize, Serialize};
 use tokio::{fs, io::AsyncWriteExt, net::TcpListener, sync::mpsc, task::spawn_blocking};
 use tracing::info;
 use tracing_subscriber::EnvFilter;
 
+mod chunker;
+mod crypto;
 mod db;
-use db::{
-    get_recent_peer_addrs, init_schema, open_db, set_config, set_local_key, upsert_manifest,
-    upsert_peer, upsert_peer_addr,
-};
-
-// P2P
-use futures::StreamExt;
-use libp2p::{
-    // added imports
-    core::ConnectedPoint,
-    identity,
-    mdns,
-    multiaddr::Protocol,
-    ping,
-    swarm::{NetworkBehaviour, SwarmEvent},
-    Multiaddr,
-    PeerId,
-    Swarm,
-};
+#[cfg(feature = "fuse")]
+mod fuse;
+mod p2p;
+use db::{get_recent_peer_addrs, open_encrypted_pool, open_pool, upsert_manifest};
+use tokio::sync::oneshot;
 
 #[derive(Parser, Debug)]
 #[command(name = "PuppyCloud", version)]
@@ -61,21 +52,83 @@ struct Cli {
     /// Multiaddr(s) of peers to dial on startup. Repeat --peer to add more.
     #[arg(long, value_name = "ADDR")]
     peer: Vec<String>,
+
+    /// Passphrase to encrypt the keystore and user database at rest. When
+    /// unset, the database is stored in plaintext as before.
+    #[arg(long, env = "PUPPYCLOUD_DB_PASSPHRASE")]
+    db_passphrase: Option<String>,
+
+    /// Human-readable name advertised to peers via the node-info exchange.
+    #[arg(long, default_value = "puppycloud-node")]
+    node_name: String,
+
+    /// Multiaddr(s) of Kademlia bootstrap nodes. Repeat --bootstrap to add more.
+    #[arg(long, value_name = "ADDR")]
+    bootstrap: Vec<String>,
+
+    /// Disable mDNS peer discovery; useful on hostile or cloud networks where
+    /// LAN multicast either leaks information or never finds anyone.
+    #[arg(long)]
+    no_mdns: bool,
+
+    /// Maximum number of established connections accepted at once, across all
+    /// peers. Each individual peer is always capped at one connection.
+    #[arg(long, default_value_t = 256)]
+    max_connections: u32,
+
+    /// Passphrase used to derive the master key chunk files are encrypted
+    /// under at rest. When unset, chunks are stored as plaintext as before.
+    #[arg(long, env = "PUPPYCLOUD_CHUNK_PASSPHRASE")]
+    chunk_passphrase: Option<String>,
+
+    /// Directory to mount a read-only FUSE view of `--fuse-root` at. Requires
+    /// the `fuse` feature.
+    #[cfg(feature = "fuse")]
+    #[arg(long, value_name = "PATH")]
+    fuse_mount: Option<String>,
+
+    /// Manifest id or generation name exposed at the root of `--fuse-mount`.
+    #[cfg(feature = "fuse")]
+    #[arg(long, value_name = "ID_OR_NAME")]
+    fuse_root: Option<String>,
+
+    /// How long an unreferenced chunk file must sit on disk before chunk GC
+    /// reclaims it, protecting an upload whose chunks have landed but whose
+    /// manifest hasn't been committed yet.
+    #[arg(long, default_value_t = 3600)]
+    gc_grace_secs: u64,
+
+    /// Run chunk GC automatically on this interval, in seconds. Unset by
+    /// default; GC only runs when `/admin/gc` is called.
+    #[arg(long, value_name = "SECS")]
+    gc_interval_secs: Option<u64>,
 }
 
 #[derive(Clone)]
 struct AppState {
-    db: Arc<Mutex<Connection>>,
+    db: db::Pool,
+    db_cipher: Option<crypto::Cipher>,
+    chunk_cipher: Option<crypto::CipherEngine>,
     data_root: PathBuf,
+    gc_grace_secs: u64,
     // P2P
     p2p_peer_id: String,
     p2p_addrs: Arc<Mutex<Vec<String>>>,
+    p2p_connected_count: Arc<Mutex<usize>>,
+    p2p_bandwidth: Arc<p2p::Bandwidth>,
     p2p_dial_tx: mpsc::Sender<String>,
+    p2p_fetch_tx: mpsc::Sender<(p2p::FetchRequest, oneshot::Sender<p2p::FetchResponse>)>,
+    p2p_gossip_tx: mpsc::Sender<p2p::ManifestAnnouncement>,
+    p2p_pair_tx: mpsc::Sender<(String, String)>,
+    p2p_provide_tx: mpsc::Sender<String>,
+    p2p_kad_fetch_tx: mpsc::Sender<(p2p::FetchRequest, oneshot::Sender<p2p::FetchResponse>)>,
     invites: Arc<Mutex<HashMap<String, i64>>>, // password -> expiry unix timestamp
-    // auth
-    sessions: Arc<Mutex<HashMap<String, String>>>, // session_id -> username
 }
 
+/// How long a session cookie stays valid after its last use; `validate_session`
+/// slides this forward on every successful check, so active users never see it.
+const SESSION_TTL_SECS: i64 = 7 * 24 * 3600;
+
 #[derive(Debug, Serialize, Deserialize)]
 struct ChunkRef {
     id: String,
@@ -88,16 +141,100 @@ struct FileManifest {
     chunks: Vec<ChunkRef>,
     mime: Option<String>,
     created_ts: time::OffsetDateTime,
+    /// Present on a directory-style manifest: its child files/directories,
+    /// each itself addressed by the id of its own manifest. Absent (the
+    /// common case) for a manifest describing a single file's bytes.
+    #[serde(default)]
+    entries: Option<Vec<ManifestEntry>>,
 }
 
-fn chunk_id(data: &[u8]) -> String {
+/// One named child of a directory-style `FileManifest`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct ManifestEntry {
+    pub(crate) name: String,
+    pub(crate) manifest_id: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct CreateGenerationReq {
+    manifest_id: String,
+}
+
+#[derive(Debug, Serialize)]
+struct GenerationInfo {
+    name: String,
+    seq: i64,
+    manifest_id: String,
+    created_ts: i64,
+}
+
+impl From<db::GenerationRow> for GenerationInfo {
+    fn from(row: db::GenerationRow) -> Self {
+        GenerationInfo {
+            name: row.name,
+            seq: row.seq,
+            manifest_id: row.manifest_id,
+            created_ts: row.created_ts,
+        }
+    }
+}
+
+pub(crate) fn chunk_id(data: &[u8]) -> String {
     blake3::hash(data).to_hex().to_string()
 }
 
-fn chunk_path(root: &Path, id: &str) -> PathBuf {
+pub(crate) fn chunk_path(root: &Path, id: &str) -> PathBuf {
     root.join(&id[0..2]).join(&id[2..4]).join(id)
 }
 
+/// Writes `plaintext` (already known to hash to `id`) to `chunk_path`,
+/// encrypting it first if `chunk_cipher` is configured. No-op if the
+/// destination already exists, preserving the dedup invariant.
+async fn write_chunk(
+    data_root: &Path,
+    chunk_cipher: &Option<crypto::CipherEngine>,
+    id: &str,
+    plaintext: &[u8],
+) -> Result<(), (StatusCode, String)> {
+    let p = chunk_path(data_root, id);
+    if p.exists() {
+        return Ok(());
+    }
+    if let Some(parent) = p.parent() {
+        std::fs::create_dir_all(parent).map_err(intern)?;
+    }
+    let on_disk: &[u8] = &match chunk_cipher {
+        Some(cipher) => cipher.encrypt_chunk(id, plaintext),
+        None => plaintext.to_vec(),
+    };
+    let mut f = fs::File::create(&p).await.map_err(intern)?;
+    f.write_all(on_disk).await.map_err(intern)?;
+    f.flush().await.map_err(intern)?;
+    Ok(())
+}
+
+/// Reads the chunk stored under `id`, decrypting it if `chunk_cipher` is
+/// configured. Returns `Ok(None)` if the chunk isn't on disk, and an
+/// `INTERNAL_SERVER_ERROR` if decryption fails its AEAD tag.
+pub(crate) async fn read_chunk(
+    data_root: &Path,
+    chunk_cipher: &Option<crypto::CipherEngine>,
+    id: &str,
+) -> Result<Option<Vec<u8>>, (StatusCode, String)> {
+    let p = chunk_path(data_root, id);
+    if !p.exists() {
+        return Ok(None);
+    }
+    let data = tokio::fs::read(&p).await.map_err(intern)?;
+    match chunk_cipher {
+        Some(cipher) => cipher
+            .decrypt_chunk(id, &data)
+            .map(Some)
+            .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "chunk decryption failed".into())),
+        None => Ok(Some(data)),
+    }
+}
+
 // --- Auth extractor & handlers ---
 use argon2::{
     password_hash::{PasswordHasher, SaltString},
@@ -106,9 +243,12 @@ use argon2::{
 use axum::async_trait;
 use axum::extract::FromRequestParts;
 use axum::http::{header, request::Parts};
-use rand_core::{OsRng, RngCore};
+use rand_core::OsRng;
 
-struct RequireAuth(pub String); // username
+struct RequireAuth {
+    username: String,
+    sid: String,
+}
 
 #[async_trait]
 impl FromRequestParts<AppState> for RequireAuth {
@@ -130,11 +270,22 @@ impl FromRequestParts<AppState> for RequireAuth {
                 c.strip_prefix("sid=").map(|rest| rest.to_string())
             })
             .ok_or((StatusCode::UNAUTHORIZED, "no session".into()))?;
-        let sessions = state.sessions.lock().unwrap();
-        if let Some(user) = sessions.get(&sid) {
-            Ok(RequireAuth(user.clone()))
-        } else {
-            Err((StatusCode::UNAUTHORIZED, "invalid session".into()))
+
+        let db = state.db.clone();
+        let cipher = state.db_cipher.clone();
+        let sid_clone = sid.clone();
+        let now = time::OffsetDateTime::now_utc().unix_timestamp();
+        let user = spawn_blocking(move || -> Result<Option<db::UserRow>, rusqlite::Error> {
+            let conn = db.get().expect("db pool");
+            db::validate_session(&conn, &sid_clone, now, SESSION_TTL_SECS, cipher.as_ref())
+        })
+        .await
+        .map_err(intern)?
+        .map_err(intern)?;
+
+        match user {
+            Some(user) => Ok(RequireAuth { username: user.username, sid }),
+            None => Err((StatusCode::UNAUTHORIZED, "invalid session".into())),
         }
     }
 }
@@ -163,10 +314,11 @@ async fn post_set_password(
         .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
     let created_ts = time::OffsetDateTime::now_utc().unix_timestamp();
     let db = state.db.clone();
+    let cipher = state.db_cipher.clone();
     let salt_bytes = salt.as_str().as_bytes().to_vec();
     let hash_bytes = hash.hash.unwrap().as_bytes().to_vec();
     spawn_blocking(move || -> Result<(), rusqlite::Error> {
-        let conn = db.lock().unwrap();
+        let conn = db.get().expect("db pool");
         db::upsert_user(
             &conn,
             &req.username,
@@ -174,6 +326,7 @@ async fn post_set_password(
             &salt_bytes,
             created_ts,
             req.expires_ts,
+            cipher.as_ref(),
         )?;
         Ok(())
     })
@@ -201,10 +354,11 @@ async fn post_login(
     (StatusCode, String),
 > {
     let db = state.db.clone();
+    let cipher = state.db_cipher.clone();
     let username = req.username.clone();
     let user = spawn_blocking(move || -> Result<Option<db::UserRow>, rusqlite::Error> {
-        let conn = db.lock().unwrap();
-        db::get_user(&conn, &username)
+        let conn = db.get().expect("db pool");
+        db::get_user(&conn, &username, cipher.as_ref())
     })
     .await
     .map_err(intern)?
@@ -233,14 +387,16 @@ async fn post_login(
         return Err((StatusCode::UNAUTHORIZED, "invalid credentials".into()));
     }
 
-    let mut sid_bytes = [0u8; 32];
-    OsRng.fill_bytes(&mut sid_bytes);
-    let sid = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(sid_bytes);
-    state
-        .sessions
-        .lock()
-        .unwrap()
-        .insert(sid.clone(), req.username.clone());
+    let db = state.db.clone();
+    let now = time::OffsetDateTime::now_utc().unix_timestamp();
+    let username = req.username.clone();
+    let sid = spawn_blocking(move || -> Result<String, rusqlite::Error> {
+        let conn = db.get().expect("db pool");
+        db::create_session(&conn, &username, now, SESSION_TTL_SECS)
+    })
+    .await
+    .map_err(intern)?
+    .map_err(intern)?;
     let cookie = format!("sid={}; Path=/; HttpOnly; SameSite=Lax", sid);
 
     Ok((
@@ -252,7 +408,7 @@ async fn post_login(
 
 async fn post_logout(
     State(state): State<AppState>,
-    RequireAuth(user): RequireAuth,
+    auth: RequireAuth,
 ) -> Result<
     (
         StatusCode,
@@ -261,9 +417,15 @@ async fn post_logout(
     ),
     (StatusCode, String),
 > {
+    let db = state.db.clone();
+    spawn_blocking(move || -> Result<(), rusqlite::Error> {
+        let conn = db.get().expect("db pool");
+        db::delete_session(&conn, &auth.sid)
+    })
+    .await
+    .map_err(intern)?
+    .map_err(intern)?;
     let cookie = "sid=; Path=/; HttpOnly; Max-Age=0; SameSite=Lax".to_string();
-    let mut sessions = state.sessions.lock().unwrap();
-    sessions.retain(|_, v| v != &user);
     Ok((
         StatusCode::OK,
         [(header::SET_COOKIE, cookie)],
@@ -271,206 +433,6 @@ async fn post_logout(
     ))
 }
 
-// --- P2P setup ---
-#[derive(NetworkBehaviour)]
-struct PcBehaviour {
-    ping: ping::Behaviour,
-    mdns: mdns::tokio::Behaviour,
-}
-
-async fn spawn_p2p(
-    addrs_out: Arc<Mutex<Vec<String>>>,
-    db: Arc<Mutex<Connection>>,
-) -> Result<(String, mpsc::Sender<String>)> {
-    // Load or generate the local identity key from DB
-    let maybe_key_bytes = spawn_blocking({
-        let db = db.clone();
-        move || {
-            let conn = db.lock().unwrap();
-            db::get_local_key(&conn, "node")
-        }
-    })
-    .await
-    .map_err(|e| anyhow::anyhow!(e.to_string()))??;
-
-    let local_key = if let Some(bytes) = maybe_key_bytes {
-        identity::Keypair::from_protobuf_encoding(&bytes)
-            .map_err(|e| anyhow::anyhow!(format!("failed to decode local key: {e}")))?
-    } else {
-        let k = identity::Keypair::generate_ed25519();
-        let enc = k
-            .to_protobuf_encoding()
-            .map_err(|e| anyhow::anyhow!(format!("failed to encode local key: {e}")))?;
-        let ts = time::OffsetDateTime::now_utc().unix_timestamp();
-        // Persist the key
-        let enc_clone = enc.clone();
-        spawn_blocking({
-            let db = db.clone();
-            move || {
-                let conn = db.lock().unwrap();
-                set_local_key(&conn, "node", &enc_clone, ts)
-            }
-        })
-        .await
-        .map_err(|e| anyhow::anyhow!(e.to_string()))??;
-        k
-    };
-
-    let local_peer_id = local_key.public().to_peer_id();
-    // Store peer_id in config for easy lookup
-    let pid_str = local_peer_id.to_string();
-    spawn_blocking({
-        let db = db.clone();
-        let pid = pid_str.clone();
-        move || {
-            let conn = db.lock().unwrap();
-            set_config(&conn, "peer_id", &pid)
-        }
-    })
-    .await
-    .map_err(|e| anyhow::anyhow!(e.to_string()))??;
-
-    // Build the Swarm with TCP + Noise + Yamux
-    let mut swarm: Swarm<PcBehaviour> = libp2p::SwarmBuilder::with_existing_identity(local_key)
-        .with_tokio()
-        .with_tcp(
-            libp2p::tcp::Config::default().nodelay(true),
-            libp2p::noise::Config::new,
-            libp2p::yamux::Config::default,
-        )
-        .expect("tcp transport")
-        .with_behaviour(|key| {
-            let peer_id = key.public().to_peer_id();
-            Ok(PcBehaviour {
-                ping: ping::Behaviour::default(),
-                mdns: mdns::tokio::Behaviour::new(mdns::Config::default(), peer_id)?,
-            })
-        })
-        .expect("behaviour")
-        .build();
-
-    // Try to listen on a random TCP port; if in use, retry once
-    let addr: Multiaddr = "/ip4/0.0.0.0/tcp/0".parse().expect("valid multiaddr");
-    match swarm.listen_on(addr) {
-        Ok(_) => {}
-        Err(e) => {
-            if let libp2p::TransportError::Other(ioe) = &e {
-                if ioe.kind() == std::io::ErrorKind::AddrInUse {
-                    tracing::warn!("p2p listen addr in use, retrying on random port");
-                    let addr2: Multiaddr = "/ip4/0.0.0.0/tcp/0".parse().expect("valid multiaddr");
-                    swarm
-                        .listen_on(addr2)
-                        .map_err(|e| anyhow::anyhow!("p2p listen error: {e}"))?;
-                } else {
-                    return Err(anyhow::anyhow!("p2p listen error: {e}"));
-                }
-            } else {
-                return Err(anyhow::anyhow!("p2p listen error: {e}"));
-            }
-        }
-    }
-
-    // Channel to request dialing from HTTP handlers
-    let (dial_tx, mut dial_rx) = mpsc::channel::<String>(32);
-
-    // Event loop
-    tokio::spawn(async move {
-        loop {
-            tokio::select! {
-                // Handle dial requests from HTTP endpoint
-                Some(addr) = dial_rx.recv() => {
-                    match addr.parse::<Multiaddr>() {
-                        Ok(ma) => {
-                            // Opportunistically persist the address if it contains a /p2p/ component
-                            let addr_str = ma.to_string();
-                            if let Some(pid) = ma.iter().find_map(|p| {
-                                if let Protocol::P2p(mh) = p { PeerId::from_multihash(mh.into()).ok() } else { None }
-                            }) {
-                                let pid_str = pid.to_string();
-                                let db2 = db.clone();
-                                let ts = time::OffsetDateTime::now_utc().unix_timestamp();
-                                spawn_blocking(move || {
-                                    let conn = db2.lock().unwrap();
-                                    let _ = upsert_peer(&conn, &pid_str, Some(&addr_str), ts);
-                                    let _ = upsert_peer_addr(&conn, &pid_str, &addr_str, ts);
-                                });
-                            }
-                            if let Err(e) = swarm.dial(ma) {
-                                tracing::warn!("p2p dial error: {e}");
-                            }
-                        }
-                        Err(e) => tracing::warn!("invalid multiaddr: {e}"),
-                    }
-                }
-                // Handle libp2p events
-                ev = swarm.select_next_some() => {
-                    match ev {
-                        SwarmEvent::NewListenAddr { address, .. } => {
-                            tracing::info!("p2p listening on {address}");
-                            let mut g = addrs_out.lock().unwrap();
-                            if !g.iter().any(|a| a == &address.to_string()) {
-                                g.push(address.to_string());
-                            }
-                        }
-                        SwarmEvent::Behaviour(event) => {
-                            match event {
-                                // mDNS discovered peers -> upsert into DB with addr
-                                PcBehaviourEvent::Mdns(mdns_event) => {
-                                    match mdns_event {
-                                        mdns::Event::Discovered(list) => {
-                                            for (pid, addr) in list {
-                                                let db2 = db.clone();
-                                                let pid_str = pid.to_string();
-                                                let addr_str = addr.to_string();
-                                                let ts = time::OffsetDateTime::now_utc().unix_timestamp();
-                                                spawn_blocking(move || {
-                                                    let conn = db2.lock().unwrap();
-                                                    let _ = upsert_peer(&conn, &pid_str, Some(&addr_str), ts);
-                                                    let _ = upsert_peer_addr(&conn, &pid_str, &addr_str, ts);
-                                                });
-                                            }
-                                        }
-                                        mdns::Event::Expired(_list) => {
-                                            // optional: could mark peers as stale
-                                        }
-                                    }
-                                }
-                                // ignore ping events
-                                PcBehaviourEvent::Ping(_) => {}
-                            }
-                        }
-                        SwarmEvent::ConnectionEstablished { peer_id, endpoint, .. } => {
-                            tracing::info!("p2p connected to {peer_id}");
-                            // Persist the remote address we connected to
-                            let addr_str = match endpoint {
-                                ConnectedPoint::Dialer { address, .. } => address.to_string(),
-                                ConnectedPoint::Listener { send_back_addr, .. } => send_back_addr.to_string(),
-                            };
-                            let db2 = db.clone();
-                            let pid_str = peer_id.to_string();
-                            let ts = time::OffsetDateTime::now_utc().unix_timestamp();
-                            spawn_blocking(move || {
-                                let conn = db2.lock().unwrap();
-                                let _ = upsert_peer(&conn, &pid_str, Some(&addr_str), ts);
-                                let _ = upsert_peer_addr(&conn, &pid_str, &addr_str, ts);
-                            });
-                        }
-                        SwarmEvent::OutgoingConnectionError { peer_id, error, .. } => {
-                            tracing::warn!("p2p outgoing conn error to {:?}: {error}", peer_id);
-                        }
-                        SwarmEvent::IncomingConnectionError { error, .. } => {
-                            tracing::warn!("p2p incoming conn error: {error}");
-                        }
-                        _ => {}
-                    }
-                }
-            }
-        }
-    });
-
-    Ok((pid_str, dial_tx))
-}
-
 const INDEX_HTML: &str = include_str!("../../assets/index.html");
 const JS_HTML: &str = include_str!("../../assets/puppycloud.js");
 const CSS: &str = include_str!("../../assets/puppycloud.css");
@@ -508,15 +470,60 @@ async fn main() -> Result<()> {
     let data_root = PathBuf::from(&cli.data);
     std::fs::create_dir_all(&data_root)?;
     let db_path = PathBuf::from(&cli.db);
-    let conn = open_db(&db_path)?;
-    init_schema(&conn)?;
+    let (db, db_cipher) = match &cli.db_passphrase {
+        Some(passphrase) => {
+            let (pool, cipher) = open_encrypted_pool(&db_path, passphrase)?;
+            (pool, Some(cipher))
+        }
+        None => (open_pool(&db_path)?, None),
+    };
+    let chunk_cipher = match &cli.chunk_passphrase {
+        Some(passphrase) => Some(db::derive_chunk_cipher(&db.get()?, passphrase)?),
+        None => None,
+    };
 
-    // Wrap DB in Arc<Mutex<...>> to share with tasks
-    let db = Arc::new(Mutex::new(conn));
+    // Sweep stale sessions left over from a previous run so the table
+    // doesn't grow unbounded across restarts.
+    {
+        let db2 = db.clone();
+        let now = time::OffsetDateTime::now_utc().unix_timestamp();
+        let purged = spawn_blocking(move || -> Result<usize, rusqlite::Error> {
+            let conn = db2.get().expect("db pool");
+            db::purge_expired_sessions(&conn, now)
+        })
+        .await??;
+        if purged > 0 {
+            info!("purged {purged} expired session(s) on startup");
+        }
+    }
 
     // Start P2P node (with DB handle)
     let p2p_addrs = Arc::new(Mutex::new(Vec::<String>::new()));
-    let (p2p_peer_id, p2p_dial_tx) = spawn_p2p(p2p_addrs.clone(), db.clone()).await?;
+    let p2p_connected_count = Arc::new(Mutex::new(0usize));
+    let invites: Arc<Mutex<HashMap<String, i64>>> = Arc::new(Mutex::new(HashMap::new()));
+    let p2p::P2pHandle {
+        peer_id: p2p_peer_id,
+        dial_tx: p2p_dial_tx,
+        fetch_tx: p2p_fetch_tx,
+        gossip_tx: p2p_gossip_tx,
+        pair_tx: p2p_pair_tx,
+        provide_tx: p2p_provide_tx,
+        kad_fetch_tx: p2p_kad_fetch_tx,
+        bandwidth: p2p_bandwidth,
+    } = p2p::spawn_p2p(
+        p2p_addrs.clone(),
+        p2p_connected_count.clone(),
+        db.clone(),
+        db_cipher.clone(),
+        chunk_cipher.clone(),
+        data_root.clone(),
+        invites.clone(),
+        cli.node_name.clone(),
+        cli.bootstrap.clone(),
+        cli.no_mdns,
+        cli.max_connections,
+    )
+    .await?;
 
     // Auto-dial recent peers from DB (e.g., last 7 days, max 32)
     {
@@ -525,7 +532,7 @@ async fn main() -> Result<()> {
         let now = time::OffsetDateTime::now_utc().unix_timestamp();
         let min_last_seen = now - 7 * 24 * 3600; // 7 days
         if let Ok(list) = spawn_blocking(move || {
-            let conn = db2.lock().unwrap();
+            let conn = db2.get().expect("db pool");
             get_recent_peer_addrs(&conn, 32, Some(min_last_seen))
         })
         .await
@@ -546,18 +553,76 @@ async fn main() -> Result<()> {
         }
     }
 
+    #[cfg(feature = "fuse")]
+    if let Some(mount_point) = cli.fuse_mount.clone() {
+        let root = cli.fuse_root.clone().ok_or_else(|| {
+            anyhow::anyhow!("--fuse-mount requires --fuse-root to pick what to expose")
+        })?;
+        let root_manifest_id = {
+            let conn = db.get()?;
+            fuse::resolve_root_manifest_id(&conn, &root)?
+                .ok_or_else(|| anyhow::anyhow!("no manifest or generation named {root:?}"))?
+        };
+        let fuse_db = db.clone();
+        let fuse_chunk_cipher = chunk_cipher.clone();
+        let fuse_data_root = data_root.clone();
+        let rt = tokio::runtime::Handle::current();
+        std::thread::spawn(move || {
+            if let Err(e) = fuse::mount(
+                &mount_point,
+                fuse_db,
+                fuse_chunk_cipher,
+                fuse_data_root,
+                root_manifest_id,
+                rt,
+            ) {
+                tracing::error!("FUSE mount at {mount_point} failed: {e}");
+            }
+        });
+    }
+
     let state = AppState {
         db: db.clone(),
+        db_cipher,
+        chunk_cipher,
         data_root,
+        gc_grace_secs: cli.gc_grace_secs,
         // P2P
         p2p_peer_id,
         p2p_addrs,
+        p2p_connected_count,
+        p2p_bandwidth,
         p2p_dial_tx,
-        invites: Arc::new(Mutex::new(std::collections::HashMap::new())),
-        // auth
-        sessions: Arc::new(Mutex::new(std::collections::HashMap::new())),
+        p2p_fetch_tx,
+        p2p_gossip_tx,
+        p2p_pair_tx,
+        p2p_provide_tx,
+        p2p_kad_fetch_tx,
+        invites,
     };
 
+    if let Some(interval_secs) = cli.gc_interval_secs {
+        let gc_state = state.clone();
+        let grace = Duration::from_secs(cli.gc_grace_secs);
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(Duration::from_secs(interval_secs));
+            loop {
+                ticker.tick().await;
+                match gc_chunks(&gc_state, grace).await {
+                    Ok(report) => {
+                        if report.reclaimed_chunks > 0 {
+                            info!(
+                                "chunk GC reclaimed {} chunk(s), {} byte(s)",
+                                report.reclaimed_chunks, report.reclaimed_bytes
+                            );
+                        }
+                    }
+                    Err((_, e)) => tracing::warn!("periodic chunk GC failed: {e}"),
+                }
+            }
+        });
+    }
+
     let app = Router::new()
         .route("/puppycloud.js", get(js))
         .route("/puppycloud.css", get(css))
@@ -576,6 +641,14 @@ async fn main() -> Result<()> {
             ),
         )
         .route("/chunks/:id", get(get_chunk))
+        .route("/chunks/have", post(post_chunks_have))
+        .route("/chunks/:id", put(put_chunk))
+        .route("/manifests/:id", put(put_manifest))
+        .route("/files/:id", get(get_file))
+        .route("/generations/:name", post(post_generation))
+        .route("/generations/:name", get(get_generations))
+        .route("/generations/:name/:seq", get(get_generation_file))
+        .route("/admin/gc", post(post_admin_gc))
         .route("/p2p/info", get(get_p2p_info))
         .route("/p2p/peers", get(get_p2p_peers))
         // .route(
@@ -627,15 +700,22 @@ async fn main() -> Result<()> {
 struct P2pInfo {
     peer_id: String,
     addrs: Vec<String>,
+    connected_peers: usize,
+    bytes_in: u64,
+    bytes_out: u64,
 }
 
 async fn get_p2p_info(
     State(state): State<AppState>,
 ) -> Result<Json<P2pInfo>, (StatusCode, String)> {
     let addrs = state.p2p_addrs.lock().unwrap().clone();
+    let connected_peers = *state.p2p_connected_count.lock().unwrap();
     Ok(Json(P2pInfo {
         peer_id: state.p2p_peer_id.clone(),
         addrs,
+        connected_peers,
+        bytes_in: state.p2p_bandwidth.total_inbound(),
+        bytes_out: state.p2p_bandwidth.total_outbound(),
     }))
 }
 
@@ -644,6 +724,10 @@ struct PeerSummary {
     peer_id: String,
     last_addr: Option<String>,
     last_seen: i64,
+    node_name: Option<String>,
+    version: Option<String>,
+    free_bytes: Option<i64>,
+    chunk_count: Option<i64>,
 }
 
 async fn get_p2p_peers(
@@ -652,19 +736,23 @@ async fn get_p2p_peers(
     let db = state.db.clone();
     let peers: Vec<PeerSummary> =
         spawn_blocking(move || -> Result<Vec<PeerSummary>, rusqlite::Error> {
-            let conn = db.lock().unwrap();
+            let conn = db.get().expect("db pool");
             let mut out = Vec::new();
             let mut stmt = conn.prepare(
-                "SELECT peer_id, last_addr, last_seen FROM peers ORDER BY last_seen DESC LIMIT ?1",
+                "SELECT p.peer_id, p.last_addr, p.last_seen, n.node_name, n.version, n.free_bytes, n.chunk_count
+                 FROM peers p
+                 LEFT JOIN node_info n ON n.peer_id = p.peer_id
+                 ORDER BY p.last_seen DESC LIMIT ?1",
             )?;
             let rows = stmt.query_map(params![100i64], |row| {
-                let peer_id: String = row.get(0)?;
-                let last_addr: Option<String> = row.get(1)?;
-                let last_seen: i64 = row.get(2)?;
                 Ok(PeerSummary {
-                    peer_id,
-                    last_addr,
-                    last_seen,
+                    peer_id: row.get(0)?,
+                    last_addr: row.get(1)?,
+                    last_seen: row.get(2)?,
+                    node_name: row.get(3)?,
+                    version: row.get(4)?,
+                    free_bytes: row.get(5)?,
+                    chunk_count: row.get(6)?,
                 })
             })?;
             for r in rows {
@@ -734,9 +822,12 @@ async fn post_p2p_dial(
     };
     match exp {
         Some(exp) if now <= exp => {
+            // Hand off to the pairing channel rather than a plain dial: once the
+            // connection is up, the P2P loop fires a PairingRequest carrying this
+            // same password, so the remote side can mark us trusted too.
             state
-                .p2p_dial_tx
-                .send(req.addr.clone())
+                .p2p_pair_tx
+                .send((req.addr.clone(), req.password.clone()))
                 .await
                 .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
             Ok(Json(
@@ -788,29 +879,44 @@ async fn upload_file(
     if file_bytes.is_empty() {
         return Err((StatusCode::BAD_REQUEST, "missing file".into()));
     }
+    // The manifest is addressed by the whole file's content hash, independent
+    // of how it happens to be split into chunks below.
     let id = chunk_id(&file_bytes);
-    let p = chunk_path(&state.data_root, &id);
-    if let Some(parent) = p.parent() {
-        std::fs::create_dir_all(parent).map_err(intern)?;
+
+    let mut chunks = Vec::new();
+    for segment in chunker::split(&file_bytes) {
+        let seg_id = chunk_id(segment);
+        write_chunk(&state.data_root, &state.chunk_cipher, &seg_id, segment).await?;
+        chunks.push(ChunkRef {
+            id: seg_id,
+            size: segment.len() as u32,
+        });
     }
-    let mut f = fs::File::create(&p).await.map_err(intern)?;
-    f.write_all(&file_bytes).await.map_err(intern)?;
-    f.flush().await.map_err(intern)?;
 
     let man = FileManifest {
         total_size: file_bytes.len() as u64,
-        chunks: vec![ChunkRef {
-            id: id.clone(),
-            size: file_bytes.len() as u32,
-        }],
+        chunks,
         mime,
         created_ts: time::OffsetDateTime::now_utc(),
+        entries: None,
     };
+    Ok(Json(finalize_manifest(&state, id, man).await?))
+}
+
+/// Persists a manifest whose chunks are already on disk, and queues it for
+/// DHT provide + gossip
+/// announcement. Shared by `upload_file` and `put_manifest`, which differ
+/// only in how the chunk bytes got onto disk in the first place.
+async fn finalize_manifest(
+    state: &AppState,
+    id: String,
+    man: FileManifest,
+) -> Result<FileManifest, (StatusCode, String)> {
     let man_json = serde_json::to_string(&man).map_err(intern)?;
     let db = state.db.clone();
     let id_clone = id.clone();
     spawn_blocking(move || -> Result<(), rusqlite::Error> {
-        let conn = db.lock().unwrap();
+        let conn = db.get().expect("db pool");
         upsert_manifest(&conn, &id_clone, &man_json)?;
         Ok(())
     })
@@ -818,21 +924,484 @@ async fn upload_file(
     .map_err(intern)?
     .map_err(intern)?;
 
-    Ok(Json(man))
+    let announcement = p2p::ManifestAnnouncement {
+        manifest_id: id,
+        total_size: man.total_size,
+        chunk_ids: man.chunks.iter().map(|c| c.id.clone()).collect(),
+    };
+    for cid in &announcement.chunk_ids {
+        if let Err(e) = state.p2p_provide_tx.send(cid.clone()).await {
+            tracing::warn!("failed to queue DHT provide for {cid}: {e}");
+        }
+    }
+    if let Err(e) = state.p2p_gossip_tx.send(announcement).await {
+        tracing::warn!("failed to queue manifest gossip: {e}");
+    }
+
+    Ok(man)
+}
+
+/// Returns the ids in `wanted` that aren't already present on disk, so a
+/// client can upload only the chunks the server is actually missing.
+async fn post_chunks_have(
+    State(state): State<AppState>,
+    Json(wanted): Json<Vec<String>>,
+) -> Result<Json<Vec<String>>, (StatusCode, String)> {
+    let data_root = state.data_root.clone();
+    let missing = spawn_blocking(move || {
+        wanted
+            .into_iter()
+            .filter(|id| !chunk_path(&data_root, id).exists())
+            .collect::<Vec<String>>()
+    })
+    .await
+    .map_err(intern)?;
+    Ok(Json(missing))
+}
+
+/// Stores a single content-addressed chunk pushed directly by the client,
+/// as an alternative to `upload_file` sending the whole file at once. The
+/// id is trusted only after the content hash is checked against it.
+async fn put_chunk(
+    State(state): State<AppState>,
+    _auth: RequireAuth,
+    AxPath(id): AxPath<String>,
+    data: Bytes,
+) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
+    if !p2p::verify_chunk(&id, &data) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "chunk id does not match its contents".into(),
+        ));
+    }
+    write_chunk(&state.data_root, &state.chunk_cipher, &id, &data).await?;
+    Ok(Json(serde_json::json!({ "stored": id })))
+}
+
+/// Finishes an upload that pushed its chunks individually through
+/// `put_chunk`: stores the manifest referencing them, after checking every
+/// referenced chunk is actually present so a bad client can't register a
+/// manifest for data the server never received.
+async fn put_manifest(
+    State(state): State<AppState>,
+    _auth: RequireAuth,
+    AxPath(id): AxPath<String>,
+    Json(man): Json<FileManifest>,
+) -> Result<Json<FileManifest>, (StatusCode, String)> {
+    let data_root = state.data_root.clone();
+    let chunk_ids: Vec<String> = man.chunks.iter().map(|c| c.id.clone()).collect();
+    let missing = spawn_blocking(move || {
+        chunk_ids
+            .into_iter()
+            .filter(|cid| !chunk_path(&data_root, cid).exists())
+            .collect::<Vec<String>>()
+    })
+    .await
+    .map_err(intern)?;
+    if !missing.is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("missing chunks: {}", missing.join(", ")),
+        ));
+    }
+
+    // upsert_manifest is INSERT OR REPLACE, so without this check any
+    // authenticated user could PUT an existing manifest id with different
+    // chunks and silently clobber someone else's content-addressed manifest.
+    let man_json = serde_json::to_string(&man).map_err(intern)?;
+    let db = state.db.clone();
+    let id_clone = id.clone();
+    let existing = spawn_blocking(move || {
+        let conn = db.get().expect("db pool");
+        db::get_manifest(&conn, &id_clone)
+    })
+    .await
+    .map_err(intern)?
+    .map_err(intern)?;
+    if matches!(&existing, Some(existing_json) if *existing_json != man_json) {
+        return Err((
+            StatusCode::CONFLICT,
+            "manifest id already exists with different content".into(),
+        ));
+    }
+
+    Ok(Json(finalize_manifest(&state, id, man).await?))
+}
+
+/// Records an already-uploaded manifest as the next generation of `name`,
+/// building up a named file's version history one upload at a time.
+async fn post_generation(
+    State(state): State<AppState>,
+    _auth: RequireAuth,
+    AxPath(name): AxPath<String>,
+    Json(req): Json<CreateGenerationReq>,
+) -> Result<Json<GenerationInfo>, (StatusCode, String)> {
+    let now = time::OffsetDateTime::now_utc().unix_timestamp();
+    let db = state.db.clone();
+    let name_clone = name.clone();
+    let manifest_id = req.manifest_id.clone();
+    let seq = spawn_blocking(move || -> rusqlite::Result<Option<i64>> {
+        let conn = db.get().expect("db pool");
+        if db::get_manifest(&conn, &manifest_id)?.is_none() {
+            return Ok(None);
+        }
+        Ok(Some(db::create_generation(&conn, &name_clone, &manifest_id, now)?))
+    })
+    .await
+    .map_err(intern)?
+    .map_err(intern)?;
+
+    match seq {
+        Some(seq) => Ok(Json(GenerationInfo { name, seq, manifest_id: req.manifest_id, created_ts: now })),
+        None => Err((StatusCode::BAD_REQUEST, "unknown manifest id".into())),
+    }
+}
+
+/// Lists every generation recorded for `name`, oldest first.
+async fn get_generations(
+    State(state): State<AppState>,
+    AxPath(name): AxPath<String>,
+) -> Result<Json<Vec<GenerationInfo>>, (StatusCode, String)> {
+    let db = state.db.clone();
+    let name_clone = name.clone();
+    let rows = spawn_blocking(move || -> rusqlite::Result<Vec<db::GenerationRow>> {
+        let conn = db.get().expect("db pool");
+        db::list_generations(&conn, &name_clone)
+    })
+    .await
+    .map_err(intern)?
+    .map_err(intern)?;
+    Ok(Json(rows.into_iter().map(GenerationInfo::from).collect()))
+}
+
+/// Downloads one specific generation of `name` by reassembling the manifest
+/// it points at, honoring `Range` the same way `get_file` does.
+async fn get_generation_file(
+    State(state): State<AppState>,
+    AxPath((name, seq)): AxPath<(String, i64)>,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, String)> {
+    let db = state.db.clone();
+    let name_clone = name.clone();
+    let row = spawn_blocking(move || -> rusqlite::Result<Option<db::GenerationRow>> {
+        let conn = db.get().expect("db pool");
+        db::get_generation(&conn, &name_clone, seq)
+    })
+    .await
+    .map_err(intern)?
+    .map_err(intern)?;
+    let row = match row {
+        Some(row) => row,
+        None => return Err((StatusCode::NOT_FOUND, "not found".into())),
+    };
+
+    let man = fetch_manifest(&state, &row.manifest_id).await?;
+    stream_manifest(&state, man, &headers).await
+}
+
+#[derive(Debug, Serialize)]
+struct GcReport {
+    referenced_chunks: usize,
+    reclaimed_chunks: usize,
+    reclaimed_bytes: u64,
+}
+
+/// Mark-and-sweep GC for the filesystem-backed chunk store: every manifest
+/// ever stored (including old generations - once inserted, a manifest's id
+/// lives in `manifests` forever) is deserialized to build the set of
+/// referenced chunk ids, then `data_root`'s sharded directory tree is walked
+/// and any chunk file not in that set is deleted, unless it's younger than
+/// `grace_period` (which protects an upload that wrote its chunks but
+/// hasn't committed its manifest yet).
+async fn gc_chunks(state: &AppState, grace_period: Duration) -> Result<GcReport, (StatusCode, String)> {
+    let db = state.db.clone();
+    let manifest_jsons = spawn_blocking(move || -> rusqlite::Result<Vec<String>> {
+        let conn = db.get().expect("db pool");
+        db::all_manifest_jsons(&conn)
+    })
+    .await
+    .map_err(intern)?
+    .map_err(intern)?;
+
+    let mut referenced: HashSet<String> = HashSet::new();
+    for json in &manifest_jsons {
+        if let Ok(man) = serde_json::from_str::<FileManifest>(json) {
+            referenced.extend(man.chunks.into_iter().map(|c| c.id));
+        }
+    }
+
+    let data_root = state.data_root.clone();
+    let cutoff = SystemTime::now() - grace_period;
+    spawn_blocking(move || -> std::io::Result<GcReport> {
+        let mut reclaimed_chunks = 0usize;
+        let mut reclaimed_bytes = 0u64;
+        for path in walk_files(&data_root)? {
+            let id = match path.file_name().and_then(|n| n.to_str()) {
+                Some(n) => n.to_string(),
+                None => continue,
+            };
+            if referenced.contains(&id) {
+                continue;
+            }
+            let meta = std::fs::metadata(&path)?;
+            if meta.modified()? > cutoff {
+                continue; // too young; may be an in-flight upload
+            }
+            reclaimed_bytes += meta.len();
+            std::fs::remove_file(&path)?;
+            reclaimed_chunks += 1;
+        }
+        Ok(GcReport {
+            referenced_chunks: referenced.len(),
+            reclaimed_chunks,
+            reclaimed_bytes,
+        })
+    })
+    .await
+    .map_err(intern)?
+    .map_err(intern)
+}
+
+/// Recursively lists every regular file under `root`.
+pub(crate) fn walk_files(root: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut dirs = vec![root.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let entries = match std::fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        for entry in entries {
+            let entry = entry?;
+            let path = entry.path();
+            if entry.file_type()?.is_dir() {
+                dirs.push(path);
+            } else {
+                files.push(path);
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// Admin endpoint that runs chunk GC on demand, using the same grace period
+/// the periodic background sweep (if enabled) uses.
+async fn post_admin_gc(
+    State(state): State<AppState>,
+    _auth: RequireAuth,
+) -> Result<Json<GcReport>, (StatusCode, String)> {
+    Ok(Json(gc_chunks(&state, Duration::from_secs(state.gc_grace_secs)).await?))
 }
 
 async fn get_chunk(
     State(state): State<AppState>,
     AxPath(id): AxPath<String>,
 ) -> Result<(StatusCode, Bytes), (StatusCode, String)> {
-    let p = chunk_path(&state.data_root, &id);
-    if p.exists() {
-        let data = tokio::fs::read(p).await.map_err(intern)?;
+    if let Some(data) = read_chunk(&state.data_root, &state.chunk_cipher, &id).await? {
         return Ok((StatusCode::OK, Bytes::from(data)));
     }
+
+    // Not on disk locally; ask a directly-connected peer for it first.
+    let (reply_tx, reply_rx) = oneshot::channel();
+    if state
+        .p2p_fetch_tx
+        .send((p2p::FetchRequest::GetChunk(id.clone()), reply_tx))
+        .await
+        .is_ok()
+    {
+        if let Ok(p2p::FetchResponse::Chunk(data)) = reply_rx.await {
+            return store_and_return_chunk(&state, &id, data).await;
+        }
+    }
+
+    // No directly-connected peer has it; fall back to a Kademlia provider lookup.
+    let (reply_tx, reply_rx) = oneshot::channel();
+    if state
+        .p2p_kad_fetch_tx
+        .send((p2p::FetchRequest::GetChunk(id.clone()), reply_tx))
+        .await
+        .is_ok()
+    {
+        if let Ok(p2p::FetchResponse::Chunk(data)) = reply_rx.await {
+            return store_and_return_chunk(&state, &id, data).await;
+        }
+    }
+
     Err((StatusCode::NOT_FOUND, "not found".into()))
 }
 
+/// Verifies fetched chunk bytes against their content address, persists them
+/// locally (encrypting them first if the node encrypts chunks at rest), and
+/// returns the plaintext to the HTTP caller.
+async fn store_and_return_chunk(
+    state: &AppState,
+    id: &str,
+    data: Vec<u8>,
+) -> Result<(StatusCode, Bytes), (StatusCode, String)> {
+    if !p2p::verify_chunk(id, &data) {
+        return Err((StatusCode::INTERNAL_SERVER_ERROR, "peer returned mismatched chunk".into()));
+    }
+    write_chunk(&state.data_root, &state.chunk_cipher, id, &data).await?;
+    Ok((StatusCode::OK, Bytes::from(data)))
+}
+
+/// Streams a whole file back to the caller by concatenating its manifest's
+/// chunks in order, without ever buffering the full reassembled file. A
+/// `Range` header is honored by trimming/skipping whole chunks outside the
+/// requested interval and slicing the chunks at its edges.
+async fn get_file(
+    State(state): State<AppState>,
+    AxPath(id): AxPath<String>,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, String)> {
+    let man = fetch_manifest(&state, &id).await?;
+    stream_manifest(&state, man, &headers).await
+}
+
+/// Fetches and parses the manifest stored under `id`, or a `404` if there is none.
+async fn fetch_manifest(state: &AppState, id: &str) -> Result<FileManifest, (StatusCode, String)> {
+    let db = state.db.clone();
+    let id = id.to_string();
+    let manifest_json = spawn_blocking(move || -> rusqlite::Result<Option<String>> {
+        let conn = db.get().expect("db pool");
+        db::get_manifest(&conn, &id)
+    })
+    .await
+    .map_err(intern)?
+    .map_err(intern)?;
+    match manifest_json {
+        Some(json) => serde_json::from_str(&json).map_err(intern),
+        None => Err((StatusCode::NOT_FOUND, "not found".into())),
+    }
+}
+
+/// Streams a manifest's reassembled file contents back to the caller,
+/// honoring an optional `Range` header the same way `get_file` does.
+async fn stream_manifest(
+    state: &AppState,
+    man: FileManifest,
+    headers: &HeaderMap,
+) -> Result<Response, (StatusCode, String)> {
+    let range_header = headers.get(header::RANGE).and_then(|v| v.to_str().ok());
+    let byte_range = match range_header {
+        Some(v) => match parse_range(v, man.total_size) {
+            Some(r) => Some(r),
+            None => {
+                return Ok((
+                    StatusCode::RANGE_NOT_SATISFIABLE,
+                    [(header::CONTENT_RANGE, format!("bytes */{}", man.total_size))],
+                )
+                    .into_response());
+            }
+        },
+        None => None,
+    };
+    let (status, start, end) = match byte_range {
+        Some((start, end)) => (StatusCode::PARTIAL_CONTENT, start, end),
+        None => (StatusCode::OK, 0, man.total_size.saturating_sub(1)),
+    };
+    let content_length = end.saturating_sub(start) + 1;
+
+    let data_root = state.data_root.clone();
+    let chunk_cipher = state.chunk_cipher.clone();
+    let plan = plan_chunk_slices(&man.chunks, start, end);
+    let body_stream = futures::stream::iter(plan).then(move |slice| {
+        let data_root = data_root.clone();
+        let chunk_cipher = chunk_cipher.clone();
+        async move {
+            let data = read_chunk(&data_root, &chunk_cipher, &slice.id)
+                .await
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e.1))?
+                .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "chunk missing"))?;
+            let from = slice.skip.min(data.len());
+            let to = (slice.skip + slice.take).min(data.len());
+            Ok::<Bytes, std::io::Error>(Bytes::copy_from_slice(&data[from..to]))
+        }
+    });
+
+    let mut builder = Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, man.mime.as_deref().unwrap_or("application/octet-stream"))
+        .header(header::ACCEPT_RANGES, "bytes")
+        .header(header::CONTENT_LENGTH, content_length.to_string());
+    if status == StatusCode::PARTIAL_CONTENT {
+        builder = builder.header(
+            header::CONTENT_RANGE,
+            format!("bytes {start}-{end}/{}", man.total_size),
+        );
+    }
+    builder
+        .body(Body::from_stream(body_stream))
+        .map_err(intern)
+}
+
+/// Parses a single-range `Range: bytes=...` header value (`start-end`,
+/// `start-`, or `-suffix_len`) into an inclusive `[start, end]` byte
+/// interval, or `None` if the header is malformed, a multi-range request,
+/// or unsatisfiable against `total_size`.
+fn parse_range(value: &str, total_size: u64) -> Option<(u64, u64)> {
+    let spec = value.strip_prefix("bytes=")?;
+    if spec.contains(',') {
+        return None;
+    }
+    let (start_s, end_s) = spec.split_once('-')?;
+    if start_s.is_empty() {
+        let suffix_len: u64 = end_s.parse().ok()?;
+        if suffix_len == 0 || total_size == 0 {
+            return None;
+        }
+        let suffix_len = suffix_len.min(total_size);
+        return Some((total_size - suffix_len, total_size - 1));
+    }
+    let start: u64 = start_s.parse().ok()?;
+    if start >= total_size {
+        return None;
+    }
+    let end = if end_s.is_empty() {
+        total_size - 1
+    } else {
+        end_s.parse().ok()?
+    };
+    if start > end {
+        return None;
+    }
+    Some((start, end.min(total_size - 1)))
+}
+
+/// One chunk's contribution to a requested byte range: how many bytes to
+/// skip from its start, and how many to take after that.
+struct ChunkSlice {
+    id: String,
+    skip: usize,
+    take: usize,
+}
+
+/// Maps an inclusive `[start, end]` byte interval of the reassembled file
+/// onto the subset of `chunks` that overlap it, with per-chunk skip/take
+/// offsets so the caller only reads the bytes it actually needs.
+fn plan_chunk_slices(chunks: &[ChunkRef], start: u64, end: u64) -> Vec<ChunkSlice> {
+    let mut plan = Vec::new();
+    let mut offset: u64 = 0;
+    for c in chunks {
+        let chunk_start = offset;
+        let chunk_end = offset + c.size as u64 - 1;
+        offset += c.size as u64;
+        if chunk_end < start || chunk_start > end {
+            continue;
+        }
+        let skip = start.saturating_sub(chunk_start) as usize;
+        let last = end.min(chunk_end);
+        let take = (last - chunk_start + 1) as usize - skip;
+        plan.push(ChunkSlice {
+            id: c.id.clone(),
+            skip,
+            take,
+        });
+    }
+    plan
+}
+
 fn intern<E: std::fmt::Display>(e: E) -> (StatusCode, String) {
     (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
 }
@@ -848,6 +1417,49 @@ mod tests {
         assert_eq!(id, blake3::hash(data).to_hex().to_string());
     }
 
+    #[test]
+    fn parse_range_handles_start_end_and_suffix_forms() {
+        assert_eq!(parse_range("bytes=0-99", 1000), Some((0, 99)));
+        assert_eq!(parse_range("bytes=500-", 1000), Some((500, 999)));
+        assert_eq!(parse_range("bytes=-100", 1000), Some((900, 999)));
+        assert_eq!(parse_range("bytes=0-999999", 1000), Some((0, 999)));
+        assert_eq!(parse_range("bytes=1000-1001", 1000), None);
+        assert_eq!(parse_range("bytes=0-10,20-30", 1000), None);
+        assert_eq!(parse_range("nonsense", 1000), None);
+    }
+
+    #[test]
+    fn plan_chunk_slices_covers_a_range_spanning_multiple_chunks() {
+        let chunks = vec![
+            ChunkRef { id: "a".into(), size: 10 },
+            ChunkRef { id: "b".into(), size: 10 },
+            ChunkRef { id: "c".into(), size: 10 },
+        ];
+        let plan = plan_chunk_slices(&chunks, 5, 24);
+        assert_eq!(plan.len(), 3);
+        assert_eq!((plan[0].id.as_str(), plan[0].skip, plan[0].take), ("a", 5, 5));
+        assert_eq!((plan[1].id.as_str(), plan[1].skip, plan[1].take), ("b", 0, 10));
+        assert_eq!((plan[2].id.as_str(), plan[2].skip, plan[2].take), ("c", 0, 5));
+    }
+
+    #[test]
+    fn walk_files_finds_nested_files_but_not_directories() {
+        let dir = std::env::temp_dir().join(format!("puppycloud-test-{}", chunk_id(b"walk_files")));
+        std::fs::create_dir_all(dir.join("ab/cd")).unwrap();
+        std::fs::write(dir.join("ab/cd/chunk-one"), b"one").unwrap();
+        std::fs::write(dir.join("ab/chunk-two"), b"two").unwrap();
+
+        let mut found: Vec<String> = walk_files(&dir)
+            .unwrap()
+            .into_iter()
+            .map(|p| p.file_name().unwrap().to_str().unwrap().to_string())
+            .collect();
+        found.sort();
+        assert_eq!(found, vec!["chunk-one", "chunk-two"]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
     #[test]
     fn test_chunk_path() {
         let root = PathBuf::from("/tmp/data");
@@ -873,6 +1485,7 @@ mod tests {
             }],
             mime: None,
             created_ts: time::OffsetDateTime::now_utc(),
+            entries: None,
         };
         let man_json = serde_json::to_string(&man).unwrap();
         conn.execute(
@@ -0,0 +1,1047 @@
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Result;
+use futures::StreamExt;
+use libp2p::{
+    bandwidth::BandwidthSinks,
+    connection_limits,
+    core::ConnectedPoint,
+    gossipsub, identify, identity, kad,
+    mdns,
+    multiaddr::Protocol,
+    ping,
+    request_response::{self, OutboundRequestId, ProtocolSupport},
+    swarm::{behaviour::toggle::Toggle, NetworkBehaviour, SwarmEvent},
+    Multiaddr, PeerId, StreamProtocol, Swarm,
+};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    sync::{mpsc, oneshot},
+    task::spawn_blocking,
+};
+
+use crate::{chunk_id, chunk_path, crypto::Cipher, crypto::CipherEngine, db};
+
+/// Gossip topic new manifests are announced on.
+const MANIFEST_TOPIC: &str = "puppycloud/manifests";
+
+/// Each peer may only hold one established connection to us at a time; the
+/// `connection_limits` behaviour denies excess `IncomingConnection`s for an
+/// already-connected peer before they ever reach the rest of the stack.
+const MAX_CONNECTIONS_PER_PEER: u32 = 1;
+
+/// Compact announcement published to `MANIFEST_TOPIC` when a manifest is
+/// stored locally, so peers can replicate without being dialed directly.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManifestAnnouncement {
+    pub manifest_id: String,
+    pub total_size: u64,
+    pub chunk_ids: Vec<String>,
+}
+
+/// Derives a gossipsub message id from the manifest id rather than the raw
+/// bytes, so re-announcing the same manifest (e.g. after a restart) doesn't
+/// cause it to circulate the mesh again.
+fn manifest_message_id(message: &gossipsub::Message) -> gossipsub::MessageId {
+    match serde_json::from_slice::<ManifestAnnouncement>(&message.data) {
+        Ok(ann) => gossipsub::MessageId::from(blake3::hash(ann.manifest_id.as_bytes()).to_hex().to_string()),
+        Err(_) => gossipsub::MessageId::from(blake3::hash(&message.data).to_hex().to_string()),
+    }
+}
+
+/// What a fetch requester asks a remote peer for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FetchRequest {
+    GetChunk(String),
+    GetManifest(String),
+}
+
+/// What a remote peer replies with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FetchResponse {
+    NotFound,
+    Chunk(Vec<u8>),
+    Manifest(Vec<u8>),
+}
+
+/// Wire codec for `FetchRequest`/`FetchResponse`: a one-byte tag followed by
+/// a u32 length prefix and the raw bytes. Kept deliberately simple rather
+/// than reaching for serde, since every field here is already a string or
+/// raw bytes.
+#[derive(Debug, Clone, Default)]
+pub struct ChunkCodec;
+
+const TAG_GET_CHUNK: u8 = 0;
+const TAG_GET_MANIFEST: u8 = 1;
+const TAG_NOT_FOUND: u8 = 0;
+const TAG_CHUNK: u8 = 1;
+const TAG_MANIFEST: u8 = 2;
+
+async fn write_frame<W: tokio::io::AsyncWrite + Unpin>(
+    io: &mut W,
+    tag: u8,
+    body: &[u8],
+) -> std::io::Result<()> {
+    io.write_all(&[tag]).await?;
+    io.write_all(&(body.len() as u32).to_be_bytes()).await?;
+    io.write_all(body).await?;
+    Ok(())
+}
+
+async fn read_frame<R: tokio::io::AsyncRead + Unpin>(io: &mut R) -> std::io::Result<(u8, Vec<u8>)> {
+    let mut tag = [0u8; 1];
+    io.read_exact(&mut tag).await?;
+    let mut len_buf = [0u8; 4];
+    io.read_exact(&mut len_buf).await?;
+    let len = u32::from_be_bytes(len_buf) as usize;
+    let mut body = vec![0u8; len];
+    io.read_exact(&mut body).await?;
+    Ok((tag[0], body))
+}
+
+#[async_trait::async_trait]
+impl request_response::Codec for ChunkCodec {
+    type Protocol = StreamProtocol;
+    type Request = FetchRequest;
+    type Response = FetchResponse;
+
+    async fn read_request<T>(&mut self, _: &Self::Protocol, io: &mut T) -> std::io::Result<Self::Request>
+    where
+        T: tokio::io::AsyncRead + Unpin + Send,
+    {
+        let (tag, body) = read_frame(io).await?;
+        let id = String::from_utf8(body).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        match tag {
+            TAG_GET_CHUNK => Ok(FetchRequest::GetChunk(id)),
+            TAG_GET_MANIFEST => Ok(FetchRequest::GetManifest(id)),
+            _ => Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "bad request tag")),
+        }
+    }
+
+    async fn read_response<T>(&mut self, _: &Self::Protocol, io: &mut T) -> std::io::Result<Self::Response>
+    where
+        T: tokio::io::AsyncRead + Unpin + Send,
+    {
+        let (tag, body) = read_frame(io).await?;
+        match tag {
+            TAG_NOT_FOUND => Ok(FetchResponse::NotFound),
+            TAG_CHUNK => Ok(FetchResponse::Chunk(body)),
+            TAG_MANIFEST => Ok(FetchResponse::Manifest(body)),
+            _ => Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "bad response tag")),
+        }
+    }
+
+    async fn write_request<T>(&mut self, _: &Self::Protocol, io: &mut T, req: Self::Request) -> std::io::Result<()>
+    where
+        T: tokio::io::AsyncWrite + Unpin + Send,
+    {
+        match req {
+            FetchRequest::GetChunk(id) => write_frame(io, TAG_GET_CHUNK, id.as_bytes()).await,
+            FetchRequest::GetManifest(id) => write_frame(io, TAG_GET_MANIFEST, id.as_bytes()).await,
+        }
+    }
+
+    async fn write_response<T>(&mut self, _: &Self::Protocol, io: &mut T, res: Self::Response) -> std::io::Result<()>
+    where
+        T: tokio::io::AsyncWrite + Unpin + Send,
+    {
+        match res {
+            FetchResponse::NotFound => write_frame(io, TAG_NOT_FOUND, &[]).await,
+            FetchResponse::Chunk(data) => write_frame(io, TAG_CHUNK, &data).await,
+            FetchResponse::Manifest(data) => write_frame(io, TAG_MANIFEST, &data).await,
+        }
+    }
+}
+
+/// An invite-password handshake: the dialer proves it holds a still-valid
+/// invite password, and on success the listener records it as trusted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PairingRequest {
+    pub password: String,
+    pub peer_id: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PairingResponse {
+    Ack { peer_id: String },
+    Denied,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PairingCodec;
+
+async fn write_str<W: tokio::io::AsyncWrite + Unpin>(io: &mut W, s: &str) -> std::io::Result<()> {
+    io.write_all(&(s.len() as u16).to_be_bytes()).await?;
+    io.write_all(s.as_bytes()).await?;
+    Ok(())
+}
+
+async fn read_str<R: tokio::io::AsyncRead + Unpin>(io: &mut R) -> std::io::Result<String> {
+    let mut len_buf = [0u8; 2];
+    io.read_exact(&mut len_buf).await?;
+    let len = u16::from_be_bytes(len_buf) as usize;
+    let mut buf = vec![0u8; len];
+    io.read_exact(&mut buf).await?;
+    String::from_utf8(buf).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+}
+
+const PAIRING_TAG_ACK: u8 = 0;
+const PAIRING_TAG_DENIED: u8 = 1;
+
+#[async_trait::async_trait]
+impl request_response::Codec for PairingCodec {
+    type Protocol = StreamProtocol;
+    type Request = PairingRequest;
+    type Response = PairingResponse;
+
+    async fn read_request<T>(&mut self, _: &Self::Protocol, io: &mut T) -> std::io::Result<Self::Request>
+    where
+        T: tokio::io::AsyncRead + Unpin + Send,
+    {
+        let password = read_str(io).await?;
+        let peer_id = read_str(io).await?;
+        Ok(PairingRequest { password, peer_id })
+    }
+
+    async fn read_response<T>(&mut self, _: &Self::Protocol, io: &mut T) -> std::io::Result<Self::Response>
+    where
+        T: tokio::io::AsyncRead + Unpin + Send,
+    {
+        let mut tag = [0u8; 1];
+        io.read_exact(&mut tag).await?;
+        match tag[0] {
+            PAIRING_TAG_ACK => Ok(PairingResponse::Ack { peer_id: read_str(io).await? }),
+            PAIRING_TAG_DENIED => Ok(PairingResponse::Denied),
+            _ => Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "bad pairing response tag")),
+        }
+    }
+
+    async fn write_request<T>(&mut self, _: &Self::Protocol, io: &mut T, req: Self::Request) -> std::io::Result<()>
+    where
+        T: tokio::io::AsyncWrite + Unpin + Send,
+    {
+        write_str(io, &req.password).await?;
+        write_str(io, &req.peer_id).await
+    }
+
+    async fn write_response<T>(&mut self, _: &Self::Protocol, io: &mut T, res: Self::Response) -> std::io::Result<()>
+    where
+        T: tokio::io::AsyncWrite + Unpin + Send,
+    {
+        match res {
+            PairingResponse::Ack { peer_id } => {
+                io.write_all(&[PAIRING_TAG_ACK]).await?;
+                write_str(io, &peer_id).await
+            }
+            PairingResponse::Denied => io.write_all(&[PAIRING_TAG_DENIED]).await,
+        }
+    }
+}
+
+/// Identity and capabilities advertised to a peer once `identify` tells us it
+/// speaks our protocols. Plain JSON over a length-prefixed frame is fine
+/// here: unlike the chunk and pairing protocols above, the fields are a mix
+/// of strings and integers rather than a single string/byte blob.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct NodeInformation {
+    pub peer_id: String,
+    pub node_name: String,
+    pub version: String,
+    pub free_bytes: u64,
+    pub chunk_count: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct NodeInfoCodec;
+
+async fn write_json<W: tokio::io::AsyncWrite + Unpin, V: Serialize>(io: &mut W, value: &V) -> std::io::Result<()> {
+    let body = serde_json::to_vec(value).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+    write_frame(io, 0, &body).await
+}
+
+async fn read_json<R: tokio::io::AsyncRead + Unpin, V: serde::de::DeserializeOwned>(io: &mut R) -> std::io::Result<V> {
+    let (_tag, body) = read_frame(io).await?;
+    serde_json::from_slice(&body).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+}
+
+#[async_trait::async_trait]
+impl request_response::Codec for NodeInfoCodec {
+    type Protocol = StreamProtocol;
+    type Request = ();
+    type Response = NodeInformation;
+
+    async fn read_request<T>(&mut self, _: &Self::Protocol, _io: &mut T) -> std::io::Result<Self::Request>
+    where
+        T: tokio::io::AsyncRead + Unpin + Send,
+    {
+        Ok(())
+    }
+
+    async fn read_response<T>(&mut self, _: &Self::Protocol, io: &mut T) -> std::io::Result<Self::Response>
+    where
+        T: tokio::io::AsyncRead + Unpin + Send,
+    {
+        read_json(io).await
+    }
+
+    async fn write_request<T>(&mut self, _: &Self::Protocol, _io: &mut T, _req: Self::Request) -> std::io::Result<()>
+    where
+        T: tokio::io::AsyncWrite + Unpin + Send,
+    {
+        Ok(())
+    }
+
+    async fn write_response<T>(&mut self, _: &Self::Protocol, io: &mut T, res: Self::Response) -> std::io::Result<()>
+    where
+        T: tokio::io::AsyncWrite + Unpin + Send,
+    {
+        write_json(io, &res).await
+    }
+}
+
+/// Reads free disk space under `path`, used to fill in `NodeInformation::free_bytes`.
+fn free_bytes_at(path: &PathBuf) -> u64 {
+    fs4::available_space(path).unwrap_or(0)
+}
+
+#[derive(NetworkBehaviour)]
+pub struct PcBehaviour {
+    ping: ping::Behaviour,
+    /// Wrapped in `Toggle` so `--no-mdns` can omit LAN discovery entirely
+    /// without needing a second `PcBehaviour` shape.
+    mdns: Toggle<mdns::tokio::Behaviour>,
+    chunks: request_response::Behaviour<ChunkCodec>,
+    gossipsub: gossipsub::Behaviour,
+    pairing: request_response::Behaviour<PairingCodec>,
+    identify: identify::Behaviour,
+    node_info: request_response::Behaviour<NodeInfoCodec>,
+    kad: kad::Behaviour<kad::store::MemoryStore>,
+    limits: connection_limits::Behaviour,
+}
+
+/// Cumulative inbound/outbound byte counters for the swarm's transport.
+/// Re-exported under our own name so callers outside this module don't need
+/// a direct `libp2p` dependency just to hold the `Arc`.
+pub type Bandwidth = BandwidthSinks;
+
+/// Handle returned by `spawn_p2p` for driving the swarm from HTTP handlers.
+pub struct P2pHandle {
+    pub peer_id: String,
+    pub dial_tx: mpsc::Sender<String>,
+    pub fetch_tx: mpsc::Sender<(FetchRequest, oneshot::Sender<FetchResponse>)>,
+    pub gossip_tx: mpsc::Sender<ManifestAnnouncement>,
+    /// (addr, invite password) — dials `addr` and, once connected, proves the
+    /// invite password to be recorded as a trusted peer.
+    pub pair_tx: mpsc::Sender<(String, String)>,
+    /// Content id to announce on the DHT as locally available.
+    pub provide_tx: mpsc::Sender<String>,
+    /// Like `fetch_tx`, but sends the request to the first provider a
+    /// Kademlia lookup returns instead of to an already-connected peer.
+    pub kad_fetch_tx: mpsc::Sender<(FetchRequest, oneshot::Sender<FetchResponse>)>,
+    /// Cumulative bytes sent/received over the transport, for `/p2p/info`.
+    pub bandwidth: Arc<Bandwidth>,
+}
+
+/// What to do once a pulled chunk comes back, depending on who asked for it.
+enum PendingFetch {
+    /// An HTTP handler is waiting on the result directly.
+    Reply(oneshot::Sender<FetchResponse>),
+    /// Auto-pulled after a gossip announcement; just persist it if it checks out.
+    AutoStore(String),
+    /// The real manifest for a gossip-announced id, fetched from the
+    /// announcing peer rather than trusting the announcement's own fields.
+    AutoStoreManifest { id: String, source: PeerId },
+}
+
+pub async fn spawn_p2p(
+    addrs_out: Arc<Mutex<Vec<String>>>,
+    connected_count_out: Arc<Mutex<usize>>,
+    db: db::Pool,
+    db_cipher: Option<Cipher>,
+    chunk_cipher: Option<CipherEngine>,
+    data_root: PathBuf,
+    invites: Arc<Mutex<HashMap<String, i64>>>,
+    node_name: String,
+    bootstrap: Vec<String>,
+    no_mdns: bool,
+    max_connections: u32,
+) -> Result<P2pHandle> {
+    // Load or generate the local identity key from DB
+    let maybe_key_bytes = spawn_blocking({
+        let db = db.clone();
+        let cipher = db_cipher.clone();
+        move || {
+            let conn = db.get().expect("db pool");
+            db::get_local_key(&conn, "node", cipher.as_ref())
+        }
+    })
+    .await
+    .map_err(|e| anyhow::anyhow!(e.to_string()))??;
+
+    let local_key = if let Some(bytes) = maybe_key_bytes {
+        identity::Keypair::from_protobuf_encoding(&bytes)
+            .map_err(|e| anyhow::anyhow!(format!("failed to decode local key: {e}")))?
+    } else {
+        let k = identity::Keypair::generate_ed25519();
+        let enc = k
+            .to_protobuf_encoding()
+            .map_err(|e| anyhow::anyhow!(format!("failed to encode local key: {e}")))?;
+        let ts = time::OffsetDateTime::now_utc().unix_timestamp();
+        let enc_clone = enc.clone();
+        let cipher = db_cipher.clone();
+        spawn_blocking({
+            let db = db.clone();
+            move || {
+                let conn = db.get().expect("db pool");
+                db::set_local_key(&conn, "node", &enc_clone, ts, cipher.as_ref())
+            }
+        })
+        .await
+        .map_err(|e| anyhow::anyhow!(e.to_string()))??;
+        k
+    };
+
+    let local_peer_id = local_key.public().to_peer_id();
+    let pid_str = local_peer_id.to_string();
+    spawn_blocking({
+        let db = db.clone();
+        let pid = pid_str.clone();
+        move || {
+            let conn = db.get().expect("db pool");
+            db::set_config(&conn, "peer_id", &pid)
+        }
+    })
+    .await
+    .map_err(|e| anyhow::anyhow!(e.to_string()))??;
+
+    spawn_blocking({
+        let db = db.clone();
+        let no_mdns_flag = if no_mdns { "1" } else { "0" }.to_string();
+        move || {
+            let conn = db.get().expect("db pool");
+            db::set_config(&conn, "no_mdns", &no_mdns_flag)
+        }
+    })
+    .await
+    .map_err(|e| anyhow::anyhow!(e.to_string()))??;
+
+    // Build the Swarm with TCP + Noise + Yamux, logging cumulative bytes
+    // moved over the transport so `/p2p/info` has something to report.
+    let (swarm_builder, bandwidth) = libp2p::SwarmBuilder::with_existing_identity(local_key)
+        .with_tokio()
+        .with_tcp(
+            libp2p::tcp::Config::default().nodelay(true),
+            libp2p::noise::Config::new,
+            libp2p::yamux::Config::default,
+        )
+        .expect("tcp transport")
+        .with_bandwidth_logging();
+
+    let mut swarm: Swarm<PcBehaviour> = swarm_builder
+        .with_behaviour(|key| {
+            let peer_id = key.public().to_peer_id();
+            let gossipsub_config = gossipsub::ConfigBuilder::default()
+                .message_id_fn(manifest_message_id)
+                .build()
+                .map_err(std::io::Error::other)?;
+            let gossipsub = gossipsub::Behaviour::new(
+                gossipsub::MessageAuthenticity::Signed(key.clone()),
+                gossipsub_config,
+            )
+            .map_err(std::io::Error::other)?;
+            let mdns = if no_mdns {
+                Toggle::from(None)
+            } else {
+                Toggle::from(Some(mdns::tokio::Behaviour::new(mdns::Config::default(), peer_id)?))
+            };
+            Ok(PcBehaviour {
+                ping: ping::Behaviour::default(),
+                mdns,
+                chunks: request_response::Behaviour::new(
+                    [(StreamProtocol::new("/puppycloud/chunks/1"), ProtocolSupport::Full)],
+                    request_response::Config::default(),
+                ),
+                gossipsub,
+                pairing: request_response::Behaviour::new(
+                    [(StreamProtocol::new("/puppycloud/pairing/1"), ProtocolSupport::Full)],
+                    request_response::Config::default(),
+                ),
+                identify: identify::Behaviour::new(
+                    identify::Config::new("/puppycloud/id/1".to_string(), key.public())
+                        .with_agent_version(format!("puppycloud/{}", env!("CARGO_PKG_VERSION"))),
+                ),
+                node_info: request_response::Behaviour::new(
+                    [(StreamProtocol::new("/puppycloud/nodeinfo/1"), ProtocolSupport::Full)],
+                    request_response::Config::default(),
+                ),
+                kad: kad::Behaviour::new(peer_id, kad::store::MemoryStore::new(peer_id)),
+                limits: connection_limits::Behaviour::new(
+                    connection_limits::ConnectionLimits::default()
+                        .with_max_established_per_peer(Some(MAX_CONNECTIONS_PER_PEER))
+                        .with_max_established(Some(max_connections)),
+                ),
+            })
+        })
+        .expect("behaviour")
+        .build();
+
+    let manifest_topic = gossipsub::IdentTopic::new(MANIFEST_TOPIC);
+    swarm
+        .behaviour_mut()
+        .gossipsub
+        .subscribe(&manifest_topic)
+        .map_err(|e| anyhow::anyhow!("failed to subscribe to {MANIFEST_TOPIC}: {e}"))?;
+
+    // Try to listen on a random TCP port; if in use, retry once
+    let addr: Multiaddr = "/ip4/0.0.0.0/tcp/0".parse().expect("valid multiaddr");
+    match swarm.listen_on(addr) {
+        Ok(_) => {}
+        Err(e) => {
+            if let libp2p::TransportError::Other(ioe) = &e {
+                if ioe.kind() == std::io::ErrorKind::AddrInUse {
+                    tracing::warn!("p2p listen addr in use, retrying on random port");
+                    let addr2: Multiaddr = "/ip4/0.0.0.0/tcp/0".parse().expect("valid multiaddr");
+                    swarm
+                        .listen_on(addr2)
+                        .map_err(|e| anyhow::anyhow!("p2p listen error: {e}"))?;
+                } else {
+                    return Err(anyhow::anyhow!("p2p listen error: {e}"));
+                }
+            } else {
+                return Err(anyhow::anyhow!("p2p listen error: {e}"));
+            }
+        }
+    }
+
+    // Seed the routing table with the configured bootstrap nodes and kick off
+    // a DHT bootstrap if any were supplied.
+    let mut have_bootstrap = false;
+    for addr in &bootstrap {
+        match addr.parse::<Multiaddr>() {
+            Ok(ma) => {
+                let peer = ma.iter().find_map(|p| {
+                    if let Protocol::P2p(mh) = p { PeerId::from_multihash(mh.into()).ok() } else { None }
+                });
+                match peer {
+                    Some(peer) => {
+                        swarm.behaviour_mut().kad.add_address(&peer, ma.clone());
+                        have_bootstrap = true;
+                    }
+                    None => tracing::warn!("bootstrap address {addr} has no /p2p/ peer id"),
+                }
+            }
+            Err(e) => tracing::warn!("invalid bootstrap multiaddr {addr}: {e}"),
+        }
+    }
+    if have_bootstrap {
+        if let Err(e) = swarm.behaviour_mut().kad.bootstrap() {
+            tracing::warn!("kademlia bootstrap failed to start: {e}");
+        }
+    }
+
+    // Channel to request dialing from HTTP handlers
+    let (dial_tx, mut dial_rx) = mpsc::channel::<String>(32);
+    // Channel for HTTP handlers to request a chunk/manifest fetch from a peer
+    let (fetch_tx, mut fetch_rx) =
+        mpsc::channel::<(FetchRequest, oneshot::Sender<FetchResponse>)>(32);
+    // Channel for HTTP handlers to announce a freshly stored manifest
+    let (gossip_tx, mut gossip_rx) = mpsc::channel::<ManifestAnnouncement>(32);
+    // Channel for HTTP handlers to dial a peer and prove an invite password
+    let (pair_tx, mut pair_rx) = mpsc::channel::<(String, String)>(32);
+    // Channel for HTTP handlers to announce a locally-stored content id on the DHT
+    let (provide_tx, mut provide_rx) = mpsc::channel::<String>(32);
+    // Channel for HTTP handlers to fetch via a Kademlia provider lookup
+    let (kad_fetch_tx, mut kad_fetch_rx) =
+        mpsc::channel::<(FetchRequest, oneshot::Sender<FetchResponse>)>(32);
+
+    // Event loop
+    tokio::spawn(async move {
+        let mut connected_peers: Vec<PeerId> = Vec::new();
+        let mut pending_fetches: HashMap<OutboundRequestId, PendingFetch> = HashMap::new();
+        let mut pending_pairings: HashMap<PeerId, String> = HashMap::new();
+        let mut pending_provider_queries: HashMap<kad::QueryId, (FetchRequest, oneshot::Sender<FetchResponse>)> =
+            HashMap::new();
+
+        loop {
+            tokio::select! {
+                // Handle dial requests from HTTP endpoint
+                Some(addr) = dial_rx.recv() => {
+                    match addr.parse::<Multiaddr>() {
+                        Ok(ma) => {
+                            let addr_str = ma.to_string();
+                            if let Some(pid) = ma.iter().find_map(|p| {
+                                if let Protocol::P2p(mh) = p { PeerId::from_multihash(mh.into()).ok() } else { None }
+                            }) {
+                                let pid_str = pid.to_string();
+                                let db2 = db.clone();
+                                let ts = time::OffsetDateTime::now_utc().unix_timestamp();
+                                spawn_blocking(move || {
+                                    let conn = db2.get().expect("db pool");
+                                    let _ = db::upsert_peer(&conn, &pid_str, Some(&addr_str), ts);
+                                    let _ = db::upsert_peer_addr(&conn, &pid_str, &addr_str, ts);
+                                });
+                            }
+                            if let Err(e) = swarm.dial(ma) {
+                                tracing::warn!("p2p dial error: {e}");
+                            }
+                        }
+                        Err(e) => tracing::warn!("invalid multiaddr: {e}"),
+                    }
+                }
+                // Handle outbound fetch requests from HTTP handlers
+                Some((req, reply)) = fetch_rx.recv() => {
+                    match connected_peers.first().copied() {
+                        Some(peer) => {
+                            let request_id = swarm.behaviour_mut().chunks.send_request(&peer, req);
+                            pending_fetches.insert(request_id, PendingFetch::Reply(reply));
+                        }
+                        None => {
+                            let _ = reply.send(FetchResponse::NotFound);
+                        }
+                    }
+                }
+                // Handle manifests to announce after a local upload
+                Some(ann) = gossip_rx.recv() => {
+                    match serde_json::to_vec(&ann) {
+                        Ok(data) => {
+                            if let Err(e) = swarm.behaviour_mut().gossipsub.publish(manifest_topic.clone(), data) {
+                                tracing::warn!("p2p manifest gossip publish failed: {e}");
+                            }
+                        }
+                        Err(e) => tracing::warn!("failed to encode manifest announcement: {e}"),
+                    }
+                }
+                // Handle dial-then-pair requests from the /p2p/dial endpoint
+                Some((addr, password)) = pair_rx.recv() => {
+                    match addr.parse::<Multiaddr>() {
+                        Ok(ma) => {
+                            let peer = ma.iter().find_map(|p| {
+                                if let Protocol::P2p(mh) = p { PeerId::from_multihash(mh.into()).ok() } else { None }
+                            });
+                            match peer {
+                                Some(peer) => {
+                                    pending_pairings.insert(peer, password);
+                                    if let Err(e) = swarm.dial(ma) {
+                                        tracing::warn!("p2p pairing dial error: {e}");
+                                        pending_pairings.remove(&peer);
+                                    }
+                                }
+                                None => tracing::warn!("pairing dial address {addr} has no /p2p/ peer id"),
+                            }
+                        }
+                        Err(e) => tracing::warn!("invalid multiaddr: {e}"),
+                    }
+                }
+                // Handle requests to announce a locally-stored content id on the DHT
+                Some(id) = provide_rx.recv() => {
+                    if let Err(e) = swarm.behaviour_mut().kad.start_providing(kad::RecordKey::new(&id)) {
+                        tracing::warn!("failed to start providing {id}: {e}");
+                    }
+                }
+                // Handle fetches that should fall back to a Kademlia provider lookup
+                Some((req, reply)) = kad_fetch_rx.recv() => {
+                    let key = match &req {
+                        FetchRequest::GetChunk(id) | FetchRequest::GetManifest(id) => kad::RecordKey::new(id),
+                    };
+                    let query_id = swarm.behaviour_mut().kad.get_providers(key);
+                    pending_provider_queries.insert(query_id, (req, reply));
+                }
+                // Handle libp2p events
+                ev = swarm.select_next_some() => {
+                    match ev {
+                        SwarmEvent::NewListenAddr { address, .. } => {
+                            tracing::info!("p2p listening on {address}");
+                            let mut g = addrs_out.lock().unwrap();
+                            if !g.iter().any(|a| a == &address.to_string()) {
+                                g.push(address.to_string());
+                            }
+                        }
+                        SwarmEvent::Behaviour(event) => {
+                            match event {
+                                PcBehaviourEvent::Mdns(mdns_event) => {
+                                    match mdns_event {
+                                        mdns::Event::Discovered(list) => {
+                                            for (pid, addr) in list {
+                                                let db2 = db.clone();
+                                                let pid_str = pid.to_string();
+                                                let addr_str = addr.to_string();
+                                                let ts = time::OffsetDateTime::now_utc().unix_timestamp();
+                                                spawn_blocking(move || {
+                                                    let conn = db2.get().expect("db pool");
+                                                    let _ = db::upsert_peer(&conn, &pid_str, Some(&addr_str), ts);
+                                                    let _ = db::upsert_peer_addr(&conn, &pid_str, &addr_str, ts);
+                                                });
+                                            }
+                                        }
+                                        mdns::Event::Expired(_list) => {
+                                            // optional: could mark peers as stale
+                                        }
+                                    }
+                                }
+                                PcBehaviourEvent::Ping(_) => {}
+                                PcBehaviourEvent::Chunks(chunks_event) => match chunks_event {
+                                    request_response::Event::Message { peer, message } => match message {
+                                        request_response::Message::Request { request, channel, .. } => {
+                                            let peer_str = peer.to_string();
+                                            let trusted = spawn_blocking({
+                                                let db = db.clone();
+                                                move || {
+                                                    let conn = db.get().expect("db pool");
+                                                    db::is_trusted(&conn, &peer_str)
+                                                }
+                                            })
+                                            .await
+                                            .unwrap_or(Ok(false))
+                                            .unwrap_or(false);
+                                            let response = if trusted {
+                                                serve_fetch(&data_root, &db, &chunk_cipher, request).await
+                                            } else {
+                                                FetchResponse::NotFound
+                                            };
+                                            let _ = swarm.behaviour_mut().chunks.send_response(channel, response);
+                                        }
+                                        request_response::Message::Response { request_id, response } => {
+                                            match pending_fetches.remove(&request_id) {
+                                                Some(PendingFetch::Reply(reply)) => {
+                                                    let _ = reply.send(response);
+                                                }
+                                                Some(PendingFetch::AutoStore(id)) => {
+                                                    if let FetchResponse::Chunk(data) = response {
+                                                        if verify_chunk(&id, &data) {
+                                                            let p = chunk_path(&data_root, &id);
+                                                            if let Some(parent) = p.parent() {
+                                                                let _ = tokio::fs::create_dir_all(parent).await;
+                                                            }
+                                                            let on_disk = match &chunk_cipher {
+                                                                Some(cipher) => cipher.encrypt_chunk(&id, &data),
+                                                                None => data,
+                                                            };
+                                                            if let Err(e) = tokio::fs::write(&p, &on_disk).await {
+                                                                tracing::warn!("failed to store auto-pulled chunk {id}: {e}");
+                                                            }
+                                                        } else {
+                                                            tracing::warn!("peer returned mismatched bytes for auto-pulled chunk {id}");
+                                                        }
+                                                    }
+                                                }
+                                                Some(PendingFetch::AutoStoreManifest { id, source }) => {
+                                                    if let FetchResponse::Manifest(data) = response {
+                                                        match String::from_utf8(data) {
+                                                            Ok(manifest_json) if serde_json::from_str::<serde_json::Value>(&manifest_json).is_ok() => {
+                                                                let db2 = db.clone();
+                                                                let source_str = source.to_string();
+                                                                spawn_blocking(move || {
+                                                                    let conn = db2.get().expect("db pool");
+                                                                    if db::allow_manifest_overwrite(&conn, &source_str, &id).unwrap_or(false) {
+                                                                        let _ = db::upsert_manifest(&conn, &id, &manifest_json);
+                                                                    }
+                                                                });
+                                                            }
+                                                            _ => {
+                                                                tracing::warn!("peer returned a malformed manifest for gossip-announced id {id}");
+                                                            }
+                                                        }
+                                                    }
+                                                }
+                                                None => {}
+                                            }
+                                        }
+                                    },
+                                    request_response::Event::OutboundFailure { request_id, error, .. } => {
+                                        tracing::warn!("p2p outbound fetch failed: {error}");
+                                        if let Some(PendingFetch::Reply(reply)) = pending_fetches.remove(&request_id) {
+                                            let _ = reply.send(FetchResponse::NotFound);
+                                        }
+                                    }
+                                    request_response::Event::InboundFailure { error, .. } => {
+                                        tracing::warn!("p2p inbound fetch failed: {error}");
+                                    }
+                                    request_response::Event::ResponseSent { .. } => {}
+                                },
+                                PcBehaviourEvent::Gossipsub(gossipsub::Event::Message { propagation_source, message, .. }) => {
+                                    if let Ok(ann) = serde_json::from_slice::<ManifestAnnouncement>(&message.data) {
+                                        let source = message.source.unwrap_or(propagation_source);
+                                        let id = ann.manifest_id.clone();
+                                        let allowed = spawn_blocking({
+                                            let db = db.clone();
+                                            let source_str = source.to_string();
+                                            let id = id.clone();
+                                            move || {
+                                                let conn = db.get().expect("db pool");
+                                                db::allow_manifest_overwrite(&conn, &source_str, &id)
+                                            }
+                                        })
+                                        .await
+                                        .unwrap_or(Ok(false))
+                                        .unwrap_or(false);
+                                        if allowed {
+                                            // Fetch the real manifest from the announcing peer instead of
+                                            // storing the announcement's own fields: ManifestAnnouncement
+                                            // doesn't carry the chunks/mime/created_ts shape every manifest
+                                            // reader (GET /files/:id, fuse.rs, gc_chunks) expects.
+                                            let request_id = swarm
+                                                .behaviour_mut()
+                                                .chunks
+                                                .send_request(&source, FetchRequest::GetManifest(id.clone()));
+                                            pending_fetches.insert(request_id, PendingFetch::AutoStoreManifest { id, source });
+                                        }
+
+                                        for cid in ann.chunk_ids {
+                                            if chunk_path(&data_root, &cid).exists() {
+                                                continue;
+                                            }
+                                            let request_id = swarm
+                                                .behaviour_mut()
+                                                .chunks
+                                                .send_request(&source, FetchRequest::GetChunk(cid.clone()));
+                                            pending_fetches.insert(request_id, PendingFetch::AutoStore(cid));
+                                        }
+                                    }
+                                }
+                                PcBehaviourEvent::Gossipsub(_) => {}
+                                PcBehaviourEvent::Pairing(pairing_event) => match pairing_event {
+                                    request_response::Event::Message { peer, message } => match message {
+                                        request_response::Message::Request { request, channel, .. } => {
+                                            let now = time::OffsetDateTime::now_utc().unix_timestamp();
+                                            let valid = invites
+                                                .lock()
+                                                .unwrap()
+                                                .get(&request.password)
+                                                .map(|exp| now <= *exp)
+                                                .unwrap_or(false);
+                                            let response = if valid {
+                                                let db2 = db.clone();
+                                                let peer_str = peer.to_string();
+                                                spawn_blocking(move || {
+                                                    let conn = db2.get().expect("db pool");
+                                                    let _ = db::trust_peer(&conn, &peer_str, now);
+                                                })
+                                                .await
+                                                .ok();
+                                                PairingResponse::Ack { peer_id: pid_str.clone() }
+                                            } else {
+                                                PairingResponse::Denied
+                                            };
+                                            let _ = swarm.behaviour_mut().pairing.send_response(channel, response);
+                                        }
+                                        request_response::Message::Response { response, .. } => match response {
+                                            PairingResponse::Ack { peer_id } => {
+                                                tracing::info!("paired with {peer_id}");
+                                            }
+                                            PairingResponse::Denied => {
+                                                tracing::warn!("pairing rejected: invalid or expired invite");
+                                            }
+                                        },
+                                    },
+                                    request_response::Event::OutboundFailure { error, .. } => {
+                                        tracing::warn!("p2p pairing request failed: {error}");
+                                    }
+                                    request_response::Event::InboundFailure { error, .. } => {
+                                        tracing::warn!("p2p pairing response failed: {error}");
+                                    }
+                                    request_response::Event::ResponseSent { .. } => {}
+                                },
+                                PcBehaviourEvent::Identify(identify::Event::Received { peer_id, info, .. }) => {
+                                    // Only peers whose identify info lists the node-info protocol
+                                    // are asked for their name/version/capacity for the /peers UI.
+                                    if info.protocols.iter().any(|p| p.as_ref() == "/puppycloud/nodeinfo/1") {
+                                        swarm.behaviour_mut().node_info.send_request(&peer_id, ());
+                                    }
+                                }
+                                PcBehaviourEvent::Identify(_) => {}
+                                PcBehaviourEvent::NodeInfo(node_info_event) => match node_info_event {
+                                    request_response::Event::Message { peer: _, message } => match message {
+                                        request_response::Message::Request { channel, .. } => {
+                                            let data_root2 = data_root.clone();
+                                            let data_root3 = data_root.clone();
+                                            let local_name = node_name.clone();
+                                            let local_pid = pid_str.clone();
+                                            let chunk_count = spawn_blocking(move || {
+                                                crate::walk_files(&data_root3).map(|files| files.len()).unwrap_or(0)
+                                            })
+                                            .await
+                                            .unwrap_or(0);
+                                            let info = NodeInformation {
+                                                peer_id: local_pid,
+                                                node_name: local_name,
+                                                version: env!("CARGO_PKG_VERSION").to_string(),
+                                                free_bytes: free_bytes_at(&data_root2),
+                                                chunk_count: chunk_count as u64,
+                                            };
+                                            let _ = swarm.behaviour_mut().node_info.send_response(channel, info);
+                                        }
+                                        request_response::Message::Response { response, .. } => {
+                                            let db2 = db.clone();
+                                            let ts = time::OffsetDateTime::now_utc().unix_timestamp();
+                                            spawn_blocking(move || {
+                                                let conn = db2.get().expect("db pool");
+                                                let _ = db::upsert_node_info(
+                                                    &conn,
+                                                    &response.peer_id,
+                                                    &response.node_name,
+                                                    &response.version,
+                                                    response.free_bytes as i64,
+                                                    response.chunk_count as i64,
+                                                    ts,
+                                                );
+                                            });
+                                        }
+                                    },
+                                    request_response::Event::OutboundFailure { error, .. } => {
+                                        tracing::warn!("p2p node-info request failed: {error}");
+                                    }
+                                    request_response::Event::InboundFailure { error, .. } => {
+                                        tracing::warn!("p2p node-info response failed: {error}");
+                                    }
+                                    request_response::Event::ResponseSent { .. } => {}
+                                },
+                                PcBehaviourEvent::Kad(kad::Event::OutboundQueryProgressed { id, result, step, .. }) => {
+                                    match result {
+                                        kad::QueryResult::GetProviders(Ok(kad::GetProvidersOk::FoundProviders {
+                                            providers, ..
+                                        })) => {
+                                            if let Some((req, reply)) = pending_provider_queries.remove(&id) {
+                                                match providers.into_iter().next() {
+                                                    // send_request dials the provider itself if not
+                                                    // already connected, using addresses Kademlia learned.
+                                                    Some(provider) => {
+                                                        let request_id = swarm.behaviour_mut().chunks.send_request(&provider, req);
+                                                        pending_fetches.insert(request_id, PendingFetch::Reply(reply));
+                                                    }
+                                                    None => {
+                                                        let _ = reply.send(FetchResponse::NotFound);
+                                                    }
+                                                }
+                                            }
+                                        }
+                                        kad::QueryResult::GetProviders(Ok(
+                                            kad::GetProvidersOk::FinishedWithNoAdditionalRecord { .. },
+                                        )) => {
+                                            if step.last {
+                                                if let Some((_, reply)) = pending_provider_queries.remove(&id) {
+                                                    let _ = reply.send(FetchResponse::NotFound);
+                                                }
+                                            }
+                                        }
+                                        kad::QueryResult::GetProviders(Err(e)) => {
+                                            tracing::warn!("kademlia get_providers failed: {e}");
+                                            if let Some((_, reply)) = pending_provider_queries.remove(&id) {
+                                                let _ = reply.send(FetchResponse::NotFound);
+                                            }
+                                        }
+                                        kad::QueryResult::Bootstrap(Err(e)) => {
+                                            tracing::warn!("kademlia bootstrap failed: {e}");
+                                        }
+                                        kad::QueryResult::StartProviding(Err(e)) => {
+                                            tracing::warn!("kademlia start_providing failed: {e}");
+                                        }
+                                        _ => {}
+                                    }
+                                }
+                                PcBehaviourEvent::Kad(_) => {}
+                                // connection_limits has no events of interest; it enforces
+                                // the caps configured above by denying connections before
+                                // they're established, which shows up as a ConnectionError.
+                                PcBehaviourEvent::Limits(_) => {}
+                            }
+                        }
+                        SwarmEvent::ConnectionEstablished { peer_id, endpoint, .. } => {
+                            tracing::info!("p2p connected to {peer_id}");
+                            if !connected_peers.contains(&peer_id) {
+                                connected_peers.push(peer_id);
+                            }
+                            *connected_count_out.lock().unwrap() = connected_peers.len();
+                            if let Some(password) = pending_pairings.remove(&peer_id) {
+                                swarm.behaviour_mut().pairing.send_request(
+                                    &peer_id,
+                                    PairingRequest { password, peer_id: pid_str.clone() },
+                                );
+                            }
+                            let addr_str = match endpoint {
+                                ConnectedPoint::Dialer { address, .. } => address.to_string(),
+                                ConnectedPoint::Listener { send_back_addr, .. } => send_back_addr.to_string(),
+                            };
+                            let db2 = db.clone();
+                            let remote_pid_str = peer_id.to_string();
+                            let ts = time::OffsetDateTime::now_utc().unix_timestamp();
+                            spawn_blocking(move || {
+                                let conn = db2.get().expect("db pool");
+                                let _ = db::upsert_peer(&conn, &remote_pid_str, Some(&addr_str), ts);
+                                let _ = db::upsert_peer_addr(&conn, &remote_pid_str, &addr_str, ts);
+                            });
+                        }
+                        SwarmEvent::ConnectionClosed { peer_id, .. } => {
+                            connected_peers.retain(|p| p != &peer_id);
+                            *connected_count_out.lock().unwrap() = connected_peers.len();
+                        }
+                        SwarmEvent::OutgoingConnectionError { peer_id, error, .. } => {
+                            tracing::warn!("p2p outgoing conn error to {:?}: {error}", peer_id);
+                        }
+                        SwarmEvent::IncomingConnectionError { error, .. } => {
+                            tracing::warn!("p2p incoming conn error: {error}");
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+    });
+
+    Ok(P2pHandle {
+        peer_id: pid_str,
+        dial_tx,
+        fetch_tx,
+        gossip_tx,
+        pair_tx,
+        provide_tx,
+        kad_fetch_tx,
+        bandwidth,
+    })
+}
+
+/// Resolves an inbound fetch request against the local block store.
+async fn serve_fetch(
+    data_root: &PathBuf,
+    db: &db::Pool,
+    chunk_cipher: &Option<CipherEngine>,
+    request: FetchRequest,
+) -> FetchResponse {
+    match request {
+        FetchRequest::GetChunk(id) => {
+            let p = chunk_path(data_root, &id);
+            match tokio::fs::read(&p).await {
+                Ok(data) => match chunk_cipher {
+                    Some(cipher) => match cipher.decrypt_chunk(&id, &data) {
+                        Ok(plaintext) => FetchResponse::Chunk(plaintext),
+                        Err(_) => FetchResponse::NotFound,
+                    },
+                    None => FetchResponse::Chunk(data),
+                },
+                Err(_) => FetchResponse::NotFound,
+            }
+        }
+        FetchRequest::GetManifest(id) => {
+            let db = db.clone();
+            let manifest = spawn_blocking(move || -> rusqlite::Result<Option<String>> {
+                let conn = db.get().expect("db pool");
+                db::get_manifest(&conn, &id)
+            })
+            .await;
+            match manifest {
+                Ok(Ok(Some(json))) => FetchResponse::Manifest(json.into_bytes()),
+                _ => FetchResponse::NotFound,
+            }
+        }
+    }
+}
+
+/// Verifies fetched chunk bytes hash to `id` before it's safe to persist
+/// them under that id, preserving the content-addressing invariant.
+pub fn verify_chunk(id: &str, data: &[u8]) -> bool {
+    chunk_id(data) == id
+}